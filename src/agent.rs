@@ -1,31 +1,60 @@
-use crate::{polar::{Angle, Radial, get_unknown_triangle_side, get_unknown_triangle_angle, add_radials}, identity::Identity};
+use crate::{polar::{Coordinate, Radial}, identity::Identity};
+use crate::clock::{self, SharedClock};
+use crate::point::Point2;
 
-use std::{time::SystemTime, alloc::System};
-use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::time::SystemTime;
 use std::collections::VecDeque;
+use std::sync::Arc;
 
+// How far short of an obstacle an agent stops when it runs into one.
+const COLLISION_MARGIN: f64 = 1e-3;
+
+// Whatever an agent can't move through, e.g. a floor plan or a map from the field.  Obstacles are treated as floor to ceiling, so 3D agents collide with them at any height.
+pub trait Obstacles: Send + Sync + Debug {
+    // How far along from to to (0.0 to 1.0) a move first hits something, or None when the way is clear.
+    fn get_collision(&self, from: Point2, to: Point2) -> Option<f64>;
+}
+
+// Agents move in the plane by default.  An `Agent<SphericalRadial>` moves in 3D with the same path following.
 #[derive(Debug, Clone)]
-pub struct Agent {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Agent<C: Coordinate = Radial> {
     pub id: Identity,
     pub origin: usize,
-    pub position: Radial,
-    pub current_coord: Option<Radial>,
-    pub path: VecDeque<Radial>,
+    pub position: C,
+    pub current_coord: Option<C>,
+    pub path: VecDeque<C>,
     // In distance per ms.  This is just a fake agent that we will eventually replace with real velocity.  This would need something like current velocity and a max velocity, with the ability to accelerate.  Since we don't actually need a smooth velocity (yet) this just approximates the data points the caller gets over time.
     pub velocity: f64,
-    pub last_update: SystemTime
+    #[cfg_attr(feature = "serde", serde(with = "crate::timestamp::millis"))]
+    pub last_update: SystemTime,
+    // Checked on every step when set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub obstacles: Option<Arc<dyn Obstacles>>,
+    // Set when the agent ran into an obstacle and gave up on its path.  Cleared by the next send_position.
+    pub collided: bool,
+    #[cfg_attr(feature = "serde", serde(skip, default = "clock::system_clock"))]
+    pub clock: SharedClock,
 }
 
-impl Agent {
-    pub fn new(id: Identity, origin: usize, position: Radial, velocity: f64) -> Agent {
+impl<C: Coordinate> Agent<C> {
+    pub fn new(id: Identity, origin: usize, position: C, velocity: f64) -> Agent<C> {
+        Agent::with_clock(id, origin, position, velocity, clock::system_clock())
+    }
+
+    pub fn with_clock(id: Identity, origin: usize, position: C, velocity: f64, clock: SharedClock) -> Agent<C> {
         Agent {
-            id: id,
-            origin: origin,
+            id,
+            origin,
             position: position.clone(),
             current_coord: None,
             path: VecDeque::new(),
-            velocity: velocity,
-            last_update: SystemTime::now()
+            velocity,
+            last_update: clock.now(),
+            obstacles: None,
+            collided: false,
+            clock,
         }
     }
 
@@ -37,59 +66,158 @@ impl Agent {
             return;
         }
 
-        self.current_coord = Some(self.position.subtract(&next_coord.as_ref().unwrap()));
-        return;
+        self.current_coord = Some(self.position.get_offset(next_coord.as_ref().unwrap()));
     }
 
     fn update_position(&mut self) {
-        if self.current_coord.is_none() && self.path.len() == 0 {
+        if self.current_coord.is_none() && self.path.is_empty() {
+            self.last_update = self.clock.now();
             return;
         }
 
-        let now = SystemTime::now();
-        let difference = now.duration_since(self.last_update).unwrap().as_micros() as f64 / 1_000.0;
+        let now = self.clock.now();
+        let difference = now.duration_since(self.last_update).unwrap_or_default().as_micros() as f64 / 1_000.0;
         self.last_update = now;
 
         let mut distance_to_travel = difference * self.velocity;
-        let mut radials: Vec<Radial> = vec![self.position.clone()];
+        let mut radials: Vec<C> = vec![self.position.clone()];
 
         while distance_to_travel > 0.0 && self.current_coord.is_some() {
-            distance_to_travel -= self.current_coord.as_ref().unwrap().radius;
+            distance_to_travel -= self.current_coord.as_ref().unwrap().get_radius();
 
             if distance_to_travel > 0.0 {
+                if self.collide(&self.current_coord.clone().unwrap()) {
+                    break;
+                }
+
                 radials.push(self.current_coord.as_ref().unwrap().clone());
-                self.position = add_radials(&radials);
+                self.position = C::add_all(&radials);
                 self.path_next();
                 radials = vec![self.position.clone()];
 
                 continue;
             }
 
-            let coord = self.current_coord.as_ref().unwrap();
+            let coord = self.current_coord.clone().unwrap();
+            let step = coord.with_radius(coord.get_radius() + distance_to_travel);
+
+            if self.collide(&step) {
+                break;
+            }
 
-            radials.push(Radial { 
-                id: coord.id.clone(),
-                radius: coord.radius + distance_to_travel, 
-                angle: coord.angle });
-            self.position = add_radials(&radials);
+            radials.push(step);
+            self.position = C::add_all(&radials);
             radials = vec![self.position.clone()];
 
-            self.current_coord.as_mut().unwrap().radius = distance_to_travel * -1.0;
+            self.current_coord = Some(coord.with_radius(-distance_to_travel));
         }
         
     }
 
-    pub fn send_position(&mut self, position: &Radial) {
+    // Moves up to just short of the first obstacle in the way of step and drops the rest of the path, since the planner has to find another way round.  Returns false when nothing is in the way.
+    fn collide(&mut self, step: &C) -> bool {
+        let obstacles = match self.obstacles.clone() {
+            Some(obstacles) => obstacles,
+            None => return false,
+        };
+
+        let to = C::add_all(&[self.position.clone(), step.clone()]);
+
+        // Steps are straight lines, so the fraction of the way along the floor plan is the fraction of the way along the step.
+        let hit = match obstacles.get_collision(self.position.get_planar(), to.get_planar()) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let travelled = (hit * step.get_radius() - COLLISION_MARGIN).max(0.0);
+        self.position = C::add_all(&[self.position.clone(), step.with_radius(travelled)]);
+        self.current_coord = None;
+        self.path.clear();
+        self.collided = true;
+
+        true
+    }
+
+    // Moves the agent onto another clock, e.g. when it joins a simulation.  It carries on from its current position as of the new clock's now, and any movement owed on the old clock is dropped so nothing depends on when this was called.
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.last_update = clock.now();
+        self.clock = clock;
+    }
+
+    pub fn send_position(&mut self, position: &C) {
+        self.collided = false;
         self.path.push_back(position.clone());
         
         if self.current_coord.is_none() {
+            // An idle agent starts moving now, not from whenever its position was last asked for.
+            self.last_update = self.clock.now();
             self.path_next();
         }
     }
     
-    pub fn get_position(&mut self) -> Radial {
+    pub fn get_position(&mut self) -> C {
         self.update_position();
 
-        return self.position.clone();
+        self.position.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use std::time::Duration;
+
+    // The segment x = 5, -1 <= y <= 1.
+    #[derive(Debug)]
+    struct Wall;
+
+    impl Obstacles for Wall {
+        fn get_collision(&self, from: Point2, to: Point2) -> Option<f64> {
+            let hit = (5.0 - from.x) / (to.x - from.x);
+            let y = from.y + hit * (to.y - from.y);
+
+            ((0.0..=1.0).contains(&hit) && (-1.0..=1.0).contains(&y)).then_some(hit)
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn agents_stop_at_obstacles() {
+        let clock = SimulatedClock::new(SystemTime::UNIX_EPOCH);
+        let mut agent = Agent::with_clock("R1".into(), 0, Radial::empty("R1".into()), 0.01, clock.shared());
+        agent.obstacles = Some(Arc::new(Wall));
+        agent.send_position(&Point2::new(10.0, 0.0).to_radial("R1".into()));
+        agent.send_position(&Point2::new(10.0, 10.0).to_radial("R1".into()));
+        clock.advance(Duration::from_secs(10));
+
+        let stopped = agent.get_position().get_planar();
+        assert!(agent.collided && agent.path.is_empty());
+        assert!((stopped.x - (5.0 - COLLISION_MARGIN)).abs() < 1e-9 && stopped.y.abs() < 1e-9, "{:?}", stopped);
+
+        // Sitting blocked for a while doesn't count towards the next path, which goes at 10 m/s from when it is sent.
+        clock.advance(Duration::from_secs(60));
+        agent.send_position(&Point2::new(stopped.x, 5.0).to_radial("R1".into()));
+        assert!(!agent.collided);
+
+        clock.advance(Duration::from_millis(200));
+        let moving = agent.get_position().get_planar();
+        assert!((moving.y - 2.0).abs() < 1e-9 && (moving.x - stopped.x).abs() < 1e-9, "{:?}", moving);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(agent.get_position().get_planar().distance(&Point2::new(stopped.x, 5.0)) < 1e-9);
+    }
+
+    #[test]
+    fn idle_time_is_not_travelled() {
+        let clock = SimulatedClock::new(SystemTime::UNIX_EPOCH);
+        let mut agent = Agent::with_clock("R1".into(), 0, Radial::empty("R1".into()), 0.001, clock.shared());
+
+        clock.advance(Duration::from_secs(1_000));
+        agent.send_position(&Point2::new(10.0, 0.0).to_radial("R1".into()));
+        clock.advance(Duration::from_millis(1));
+
+        // 1 mm in 1 ms at 1 m/s, not the 1000 s spent idle.
+        let position = agent.get_position().get_planar();
+        assert!((position.x - 0.001).abs() < 1e-9, "{:?}", position);
+    }
+}
@@ -1,46 +1,85 @@
 use std::collections::HashMap;
-use crate::agent::Agent;
+use std::sync::Arc;
+use crate::agent::{Agent, Obstacles};
+use crate::clock::{self, SharedClock};
 use crate::polar::Radial;
 use crate::identity::Identity;
 
-struct AgentManager {
+pub struct AgentManager {
     agents: HashMap<Identity, Agent>,
+    clock: SharedClock,
+    obstacles: Option<Arc<dyn Obstacles>>,
+}
+
+impl Default for AgentManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AgentManager {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        AgentManager::with_clock(clock::system_clock())
+    }
+
+    pub fn with_clock(clock: SharedClock) -> Self {
         AgentManager {
             agents: HashMap::new(),
+            clock,
+            obstacles: None,
+        }
+    }
+
+    pub fn get_clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    // Gives every agent the manager holds, and every agent added after, the same obstacles to collide with.
+    pub fn set_obstacles(&mut self, obstacles: Arc<dyn Obstacles>) {
+        for agent in self.agents.values_mut() {
+            agent.obstacles = Some(obstacles.clone());
         }
+
+        self.obstacles = Some(obstacles);
     }
 
-    fn add_agent(&mut self, agent: Agent) {
+    // Every agent the manager holds runs on the manager's clock, and collides with the manager's obstacles once it has any.
+    pub fn add_agent(&mut self, mut agent: Agent) {
+        agent.set_clock(self.clock.clone());
+
+        if let Some(obstacles) = &self.obstacles {
+            agent.obstacles = Some(obstacles.clone());
+        }
+
         self.agents.insert(agent.id.clone(), agent);
     }
 
-    fn remove_agent(&mut self, agent: &Agent) {
+    pub fn remove_agent(&mut self, agent: &Agent) {
         self.agents.remove(&agent.id);
     }
 
-    fn get_agents(&self) -> Vec<&Agent> {
+    pub fn get_agents(&self) -> Vec<&Agent> {
         self.agents.values().collect()
     }
 
-    fn get_agent(&self, agent_id: &Identity) -> Option<&Agent> {
+    pub fn get_agent(&self, agent_id: &Identity) -> Option<&Agent> {
         self.agents.get(agent_id)
     }
 
-    fn send_agent_position(&mut self, agent_id: &Identity, position: Radial) {
+    // Sorted, so anything walking the agents does it in the same order every run.
+    pub fn get_agent_ids(&self) -> Vec<Identity> {
+        let mut ids: Vec<Identity> = self.agents.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn send_agent_position(&mut self, agent_id: &Identity, position: Radial) {
         if let Some(agent) = self.agents.get_mut(agent_id) {
             agent.send_position(&position);
         }
     }
 
-    fn get_agent_position(&mut self, agent_id: &Identity) -> Option<Radial> {
-        if let Some(agent) = self.agents.get_mut(agent_id) {
-            Some(agent.get_position())
-        } else {
-            None
-        }
+    pub fn get_agent_position(&mut self, agent_id: &Identity) -> Option<Radial> {
+        self.agents.get_mut(agent_id).map(|agent| agent.get_position())
     }
 }
\ No newline at end of file
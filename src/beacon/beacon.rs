@@ -1,46 +1,104 @@
+use crate::error::{Error, Result};
+use crate::beacon::transport::{MemoryTransport, Transport};
+use crate::clock::{self, SharedClock};
+use crate::filter::DistanceFilter;
 use crate::identity::Identity;
 use crate::polar::Radial;
 use crate::signal::Signal;
+use crate::wire::{self, Reader, Writer};
 use crate::location::MomentEdge;
 
-use datetime::LocalDateTime;
-use std::collections::{VecDeque, HashMap};
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use std::thread::{self, JoinHandle, sleep, Thread};
-use std::time::{Duration, SystemTime};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 use std::sync::mpsc::{channel, Sender, Receiver};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-const LOOP_TIME: Duration = Duration::from_millis(500);
 const POLL_TIME: Duration = Duration::from_millis(50);
-
-thread_local! {
-    static BEACON_CANCEL: std::cell::Cell<bool> = std::cell::Cell::new(false);
-}
+// How long stop waits for the worker to finish before giving up on it.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BeaconSignal {
     pub id: Identity,
     pub distance: f64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timestamp::millis"))]
     pub timestamp: SystemTime,
 }
 
 impl Signal for BeaconSignal {
     fn serialize(&self) -> String {
-        format!("{} {} {}", self.id, self.distance, self.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis())
+        format!("{} {} {}", self.id, self.distance, self.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis())
+    }
+
+    // Expects "<id> <distance> <milliseconds since the epoch>".  Anything else off the radio is a parse error rather than a panic.
+    fn deserialize(data: String) -> Result<BeaconSignal> {
+        let mut split = data.split(' ');
+        let mut next = |field: &str| split.next().filter(|s| !s.is_empty()).ok_or_else(|| Error::Parse(format!("missing {} in {:?}", field, data)));
+
+        let id: Identity = next("id")?.into();
+        let distance_field = next("distance")?;
+        let timestamp_field = next("timestamp")?;
+
+        let distance = distance_field.parse::<f64>().map_err(|e| Error::Parse(format!("distance {:?}: {}", distance_field, e)))?;
+        let millis = timestamp_field.parse::<u64>().map_err(|e| Error::Parse(format!("timestamp {:?}: {}", timestamp_field, e)))?;
+
+        if !distance.is_finite() || distance < 0.0 {
+            return Err(Error::Parse(format!("distance {:?} is not a range", distance_field)));
+        }
+
+        Ok(BeaconSignal {
+            id,
+            distance,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+        })
     }
 
-    fn deserialize(data: String) -> BeaconSignal {
-        let mut split = data.split(" ");
-        let id = split.next().unwrap().into();
-        let distance = split.next().unwrap().parse::<f64>().unwrap();
-        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(split.next().unwrap().parse::<u64>().unwrap());
+    // Version 1 payload: id, distance (f64), milliseconds since the epoch (u64).
+    fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_identity(&self.id);
+        writer.write_f64(self.distance);
+        writer.write_u64(self.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+
+        wire::frame(&writer.into_bytes())
+    }
 
-        BeaconSignal {
-            id: id,
-            distance: distance,
-            timestamp: timestamp,
+    fn decode(data: &[u8]) -> Result<BeaconSignal> {
+        let (_, payload) = wire::unframe(data)?;
+        let mut reader = Reader::new(payload);
+
+        let id = reader.read_identity("id")?;
+        let distance = reader.read_f64("distance")?;
+        let millis = reader.read_u64("timestamp")?;
+
+        if !distance.is_finite() || distance < 0.0 {
+            return Err(Error::Parse(format!("distance {} is not a range", distance)));
+        }
+
+        Ok(BeaconSignal {
+            id,
+            distance,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BeaconConfig {
+    // Readings older than this are dropped from the cache.
+    pub max_age: Duration,
+    // Most readings kept per peer.
+    pub history: usize,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        BeaconConfig {
+            max_age: Duration::from_secs(5),
+            history: 16,
         }
     }
 }
@@ -48,96 +106,362 @@ impl Signal for BeaconSignal {
 pub struct Beacon {
     pub id: Identity,
     pub position: Radial,
+    pub config: BeaconConfig,
     cancel: Arc<AtomicBool>,
     queue: Option<Sender<BeaconSignal>>,
-    last_update: SystemTime,
-    distance_cache: HashMap<Identity, MomentEdge>,
+    transport: Option<Arc<dyn Transport>>,
+    worker: Option<JoinHandle<()>>,
+    // Signals the worker couldn't read off the transport, e.g. garbled frames.
+    dropped: Arc<AtomicUsize>,
+    // Readings from each peer, oldest first.
+    distance_cache: HashMap<Identity, VecDeque<MomentEdge>>,
     process_receiver: Receiver<BeaconSignal>,
     process_sender: Sender<BeaconSignal>,
-}
-
-fn wait_time() {
-    sleep(Duration::from_millis(1));
+    // Ages readings.  The worker's own polling and stop timeouts are real time whatever this says.
+    clock: SharedClock,
 }
 
 impl Beacon {
     pub fn new(id: Identity, position: Radial) -> Beacon {
+        Beacon::with_config(id, position, BeaconConfig::default())
+    }
+
+    pub fn with_config(id: Identity, position: Radial, config: BeaconConfig) -> Beacon {
+        Beacon::with_clock(id, position, config, clock::system_clock())
+    }
+
+    pub fn with_clock(id: Identity, position: Radial, config: BeaconConfig, clock: SharedClock) -> Beacon {
         let (tx, rx) = channel::<BeaconSignal>();
 
         Beacon {
-            id: id,
-            position: position,
+            id,
+            position,
+            config,
             cancel: Arc::new(AtomicBool::new(false)),
             queue: None,
-            last_update: SystemTime::now(),
+            transport: None,
+            worker: None,
+            dropped: Arc::new(AtomicUsize::new(0)),
             distance_cache: HashMap::new(),
             process_receiver: rx,
             process_sender: tx,
+            clock,
         }
     }
 
-    pub fn receive(&self, data: String) {
-        self.queue.as_ref().unwrap().send(BeaconSignal::deserialize(data)).unwrap();
+    // Fails if the packet can't be read, or if the beacon isn't listening.
+    pub fn receive(&self, data: String) -> Result<()> {
+        self.enqueue(BeaconSignal::deserialize(data)?)
+    }
+
+    // Same as receive, for binary frames.
+    pub fn receive_bytes(&self, data: &[u8]) -> Result<()> {
+        self.enqueue(BeaconSignal::decode(data)?)
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    // Hands the signal straight to processing, skipping the transport and worker, so a simulation can drive the beacon from one thread and get the same result every run.
+    pub fn inject(&self, signal: BeaconSignal) {
+        // The beacon owns the receiving end, so this can't fail.
+        let _ = self.process_sender.send(signal);
+    }
+
+    fn enqueue(&self, signal: BeaconSignal) -> Result<()> {
+        let queue = self.queue.as_ref().ok_or(Error::ChannelClosed)?;
+
+        queue.send(signal).map_err(|_| Error::ChannelClosed)
     }
 
-    fn start(&self, rx: Receiver<BeaconSignal>) {
+    // The worker hands everything heard on the transport over to the processing channel, checking for a stop every POLL_TIME while the transport is quiet.  It exits on a stop, or once the transport closes.
+    fn start(&mut self, transport: Arc<dyn Transport>) {
         let id = self.id.clone();
         let tx = self.process_sender.clone();
-        let cancel = self.cancel.clone();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let dropped = self.dropped.clone();
+        self.cancel = cancel.clone();
+        self.transport = Some(transport.clone());
 
-        thread::spawn(move || {
-            loop {
-                if cancel.load(Ordering::Acquire) {
-                    println!("received stop {}", id);
-                    break;
+        self.worker = Some(thread::spawn(move || {
+            while !cancel.load(Ordering::Acquire) {
+                match transport.recv_timeout(POLL_TIME) {
+                    // Multicast loops our own signals back, and a beacon can't range to itself.
+                    Ok(Some(signal)) if signal.id == id => continue,
+                    Ok(Some(signal)) => {
+                        // The beacon owns the other end, so this only fails while the beacon is being dropped.
+                        if tx.send(signal).is_err() {
+                            break;
+                        }
+                    },
+                    Ok(None) => continue,
+                    Err(Error::ChannelClosed) => break,
+                    // A garbled datagram or a socket hiccup shouldn't take the beacon down, so it is only counted.
+                    Err(_) => {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    },
                 }
-
-                println!("doing thing");
-                let init_time = SystemTime::now();
-                sleep(Duration::from_millis(20));
-                let finish_time = SystemTime::now();
-                println!("beacon: {} finished in {:?}", id, finish_time);
-                tx.send(BeaconSignal {id: "A0".into(), distance: 100.0, timestamp: SystemTime::now()}).unwrap();
-                sleep(LOOP_TIME - finish_time.duration_since(init_time).unwrap_or(Duration::from_millis(0)));
             }
-        
-            println!("stopping beacon");
-        });
+        }));
     }
 
-    // pub fn listen(&self, conn: VecDeque<Radial>) {
+    // Starts the worker on an in-memory transport of its own, fed by receive.  Does nothing if it is already running.
     pub fn listen(&mut self) {
-        let (tx, rx) = channel::<BeaconSignal>();
+        if self.is_running() {
+            return;
+        }
 
-        // [TODO] Mock up a listener that creates a thread to wait for a piece of data and processes every 50ms.
-        self.start(rx);
+        let transport = MemoryTransport::new();
+        self.queue = Some(transport.get_inbox());
+        self.start(Arc::new(transport));
+    }
+
+    // Starts the worker on the given transport, e.g. a MemoryBus member or a UdpTransport.  receive still works alongside it, for signals that arrive some other way.  Does nothing if it is already running.
+    pub fn listen_on(&mut self, transport: Arc<dyn Transport>) {
+        if self.is_running() {
+            return;
+        }
 
-        self.queue = Some(tx);
+        self.queue = Some(self.process_sender.clone());
+        self.start(transport);
     }
 
-    pub fn get_distances(&mut self) -> Vec<MomentEdge> {
-        let current_time = SystemTime::now();
-        if current_time.duration_since(self.last_update).unwrap().as_millis() < POLL_TIME.as_millis() {
-            return self.distance_vec();
+    // Sends the signal to every other beacon on the transport.  Fails with ChannelClosed if the beacon isn't listening.
+    pub fn broadcast(&self, signal: &BeaconSignal) -> Result<()> {
+        self.transport.as_ref().ok_or(Error::ChannelClosed)?.send(signal)
+    }
+
+    // How many signals the worker has had to drop since the beacon was made.
+    pub fn get_dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker.as_ref().is_some_and(|worker| !worker.is_finished())
+    }
+
+    // Pulls everything the worker has handed over into the cache, then drops anything stale.  Readings are kept in timestamp order, since the radio doesn't promise to deliver them in order.
+    fn ingest(&mut self) {
+        while let Ok(signal) = self.process_receiver.try_recv() {
+            let edge = MomentEdge::new(self.id.clone(), signal.id.clone(), signal.distance, signal.timestamp);
+            let history = self.distance_cache.entry(signal.id).or_default();
+            let idx = history.partition_point(|e| e.timestamp <= edge.timestamp);
+            history.insert(idx, edge);
+
+            while history.len() > self.config.history.max(1) {
+                history.pop_front();
+            }
         }
 
-        let mut next_item = self.process_receiver.try_recv();
+        self.expire(self.clock.now());
+    }
+
+    // Drops every reading older than max_age as of now, and forgets peers with nothing left.
+    pub fn expire(&mut self, now: SystemTime) {
+        let cutoff = match now.checked_sub(self.config.max_age) {
+            Some(cutoff) => cutoff,
+            None => return,
+        };
 
-        while next_item.is_ok() {
-            let signal = next_item.as_ref().unwrap();
-            self.distance_cache.entry(signal.id.clone()).or_insert(MomentEdge { left: self.id.clone(), right: signal.id.clone(), distance: signal.distance, timestamp: signal.timestamp });
+        for history in self.distance_cache.values_mut() {
+            history.retain(|edge| edge.timestamp >= cutoff);
         }
 
-        return self.distance_vec();
+        self.distance_cache.retain(|_, history| !history.is_empty());
     }
 
-    fn distance_vec(&self) -> Vec<MomentEdge> {
-        return self.distance_cache.iter().map(|x| (x.1.clone())).collect();
+    // The newest reading from every peer heard from within max_age, sorted by peer.
+    pub fn get_distances(&mut self) -> Vec<MomentEdge> {
+        self.ingest();
+
+        let mut distances: Vec<MomentEdge> = self.distance_cache.values().filter_map(|history| history.back().cloned()).collect();
+        distances.sort_by(|a, b| a.right.cmp(&b.right));
+        distances
     }
 
-    pub fn stop(&mut self) {
-        // [TODO] This doesn't work at all, wut?
+    // One reading per peer like get_distances, but with the distance run through the filter over that peer's whole history.  The timestamp is the newest reading's.
+    pub fn get_filtered_distances(&mut self, filter: &dyn DistanceFilter) -> Vec<MomentEdge> {
+        self.get_distances()
+            .into_iter()
+            .map(|newest| {
+                let samples: Vec<f64> = self.distance_cache[&newest.right].iter().map(|e| e.distance).collect();
+                MomentEdge { distance: filter.estimate(&samples).value, ..newest }
+            })
+            .collect()
+    }
+
+    // Every reading still cached from the peer, oldest first.
+    pub fn get_history(&mut self, peer: &Identity) -> Vec<MomentEdge> {
+        self.ingest();
+
+        self.distance_cache.get(peer).map(|history| history.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    // Signals the worker to stop, closes the queue, and waits up to STOP_TIMEOUT for the worker to finish.  A worker that panicked comes back as an error here rather than being lost with the thread.  Calling it when the worker isn't running does nothing.
+    pub fn stop(&mut self) -> Result<()> {
         self.cancel.store(true, Ordering::Release);
-        // drop(self.sender.take());
+        self.queue = None;
+        self.transport = None;
+
+        match self.worker.take() {
+            Some(worker) => join_worker(&self.id, worker, STOP_TIMEOUT),
+            None => Ok(()),
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for Beacon {
+    fn drop(&mut self) {
+        // Nowhere to report it from here.  Callers who care how the worker ended call stop themselves first.
+        let _ = self.stop();
+    }
+}
+
+// JoinHandle::join has no timeout, so this waits for the thread to finish on its own before joining it.  A thread still running at the deadline is left detached, since there is no way to force it to stop.
+fn join_worker(id: &Identity, worker: JoinHandle<()>, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    while !worker.is_finished() {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout(id.clone()));
+        }
+
+        sleep(Duration::from_millis(1));
+    }
+
+    worker.join().map_err(|panic| {
+        let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown panic".into(),
+        };
+
+        Error::WorkerPanicked(message)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacon::MemoryBus;
+    use crate::filter::MedianFilter;
+
+    #[test]
+    fn malformed_packets_are_errors() {
+        let signal = BeaconSignal { id: "B1".into(), distance: 12.5, timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_234) };
+        let parsed = BeaconSignal::deserialize(signal.serialize()).unwrap();
+        assert_eq!((&parsed.id, parsed.distance, parsed.timestamp), (&signal.id, signal.distance, signal.timestamp));
+
+        for packet in ["", "B1", "B1 far 10", "B1 12.5", "B1 12.5 soon", "B1 NaN 10", "B1 -1 10"] {
+            assert!(matches!(BeaconSignal::deserialize(packet.into()), Err(Error::Parse(_))), "{:?}", packet);
+        }
+
+        let beacon = Beacon::new("B0".into(), Radial::empty("B0".into()));
+        assert_eq!(beacon.receive(signal.serialize()), Err(Error::ChannelClosed));
+    }
+
+    #[test]
+    fn binary_frames_round_trip_and_evolve() {
+        let signal = BeaconSignal { id: "beacon with spaces".into(), distance: 3.25, timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(987_654) };
+        let encoded = signal.encode();
+        let decoded = BeaconSignal::decode(&encoded).unwrap();
+        assert_eq!((&decoded.id, decoded.distance, decoded.timestamp), (&signal.id, signal.distance, signal.timestamp));
+
+        let mut corrupted = encoded.clone();
+        corrupted[5] ^= 0xFF;
+        assert!(matches!(BeaconSignal::decode(&corrupted), Err(Error::Parse(_))));
+
+        // A future version with an extra field on the end still reads.
+        let mut writer = Writer::new();
+        writer.write_identity(&signal.id);
+        writer.write_f64(signal.distance);
+        writer.write_u64(987_654);
+        writer.write_f64(-42.0);
+        let mut payload = Writer::new();
+        payload.write_u8(wire::WIRE_VERSION + 1);
+        payload.write_bytes(&writer.into_bytes());
+        let mut frame = payload.into_bytes();
+        let crc = wire::crc32(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(BeaconSignal::decode(&frame).unwrap().distance, 3.25);
+    }
+
+    #[test]
+    fn worker_stops_and_reports_panics() {
+        let mut beacon = Beacon::new("B0".into(), Radial::empty("B0".into()));
+        beacon.listen();
+        assert!(beacon.is_running());
+
+        beacon.receive("B1 4.5 10".into()).unwrap();
+        assert_eq!(beacon.process_receiver.recv_timeout(STOP_TIMEOUT).unwrap().distance, 4.5);
+
+        assert_eq!(beacon.stop(), Ok(()));
+        assert!(!beacon.is_running());
+        assert_eq!(beacon.receive("B1 4.5 10".into()), Err(Error::ChannelClosed));
+
+        let panicking = thread::spawn(|| panic!("radio on fire"));
+        assert_eq!(join_worker(&"B0".into(), panicking, STOP_TIMEOUT), Err(Error::WorkerPanicked("radio on fire".into())));
+
+        let stuck = thread::spawn(|| sleep(Duration::from_millis(200)));
+        assert_eq!(join_worker(&"B0".into(), stuck, Duration::from_millis(10)), Err(Error::Timeout("B0".into())));
+    }
+
+    #[test]
+    fn beacons_hear_each_other_over_a_bus() {
+        let bus = MemoryBus::new();
+        let mut a = Beacon::new("A".into(), Radial::empty("A".into()));
+        let mut b = Beacon::new("B".into(), Radial::empty("B".into()));
+        a.listen_on(Arc::new(bus.join()));
+        b.listen_on(Arc::new(bus.join()));
+
+        a.broadcast(&BeaconSignal { id: "A".into(), distance: 6.0, timestamp: SystemTime::now() }).unwrap();
+        assert_eq!(b.process_receiver.recv_timeout(STOP_TIMEOUT).unwrap().id.as_ref(), "A");
+
+        assert_eq!((a.stop(), b.stop()), (Ok(()), Ok(())));
+        assert!(a.broadcast(&BeaconSignal { id: "A".into(), distance: 6.0, timestamp: SystemTime::now() }).is_err());
+    }
+
+    #[test]
+    fn garbled_frames_are_counted() {
+        use crate::beacon::transport::UdpTransport;
+        use std::net::UdpSocket;
+
+        let transport = UdpTransport::bind("127.0.0.1:0", vec![]).unwrap();
+        let address = transport.get_local_addr().unwrap();
+        let mut beacon = Beacon::new("B0".into(), Radial::empty("B0".into()));
+        beacon.listen_on(Arc::new(transport));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"not a frame", address).unwrap();
+        sender.send_to(&BeaconSignal { id: "B1".into(), distance: 2.0, timestamp: SystemTime::now() }.encode(), address).unwrap();
+
+        assert_eq!(beacon.process_receiver.recv_timeout(STOP_TIMEOUT).unwrap().id.as_ref(), "B1");
+        assert_eq!(beacon.get_dropped(), 1);
+        assert_eq!(beacon.stop(), Ok(()));
+    }
+
+    #[test]
+    fn distances_keep_newest_and_expire_stale() {
+        let mut beacon = Beacon::with_config("B0".into(), Radial::empty("B0".into()), BeaconConfig { max_age: Duration::from_secs(60), history: 3 });
+        let now = SystemTime::now();
+        let reading = |id: &str, distance: f64, age: u64| BeaconSignal { id: id.into(), distance, timestamp: now - Duration::from_secs(age) };
+
+        for signal in [reading("B1", 4.0, 10), reading("B1", 9.0, 1), reading("B1", 5.0, 5), reading("B1", 4.5, 20), reading("B2", 7.0, 120)] {
+            beacon.process_sender.send(signal).unwrap();
+        }
+
+        let distances = beacon.get_distances();
+        assert_eq!(distances.len(), 1, "B2 is older than max_age");
+        assert_eq!((distances[0].right.as_ref(), distances[0].distance), ("B1", 9.0));
+
+        // Only the three newest are kept, oldest first.
+        let history: Vec<f64> = beacon.get_history(&"B1".into()).iter().map(|e| e.distance).collect();
+        assert_eq!(history, vec![4.0, 5.0, 9.0]);
+        assert_eq!(beacon.get_filtered_distances(&MedianFilter)[0].distance, 5.0);
+
+        beacon.expire(now + Duration::from_secs(120));
+        assert!(beacon.get_history(&"B1".into()).is_empty());
+    }
+}
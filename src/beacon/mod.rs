@@ -1 +1,6 @@
-pub mod beacon;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod beacon;
+pub mod transport;
+
+pub use beacon::{Beacon, BeaconConfig, BeaconSignal};
+pub use transport::{MemoryBus, MemoryTransport, Transport, UdpTransport};
@@ -0,0 +1,248 @@
+// How beacons hear each other.  A transport broadcasts a signal to every other
+// beacon on it and hands back whatever the others broadcast.  MemoryTransport keeps
+// everything inside one process on mpsc channels, UdpTransport carries the binary
+// frames from wire.rs between processes, so each beacon can run as its own process
+// on one box before going to hardware.
+
+use crate::beacon::BeaconSignal;
+use crate::error::{Error, Result};
+use crate::signal::Signal;
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Largest datagram read off the socket.  Frames are a few dozen bytes, so anything near this is not ours.
+const MAX_DATAGRAM: usize = 1500;
+
+pub trait Transport: Send + Sync {
+    // Sends the signal to every other beacon on the transport.
+    fn send(&self, signal: &BeaconSignal) -> Result<()>;
+    // Waits up to timeout for the next signal.  Ok(None) when nothing arrived in time.
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<BeaconSignal>>;
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::Io(e.to_string())
+}
+
+// A shared medium for MemoryTransports, the in-process stand in for a radio channel.  Clones share the same medium.
+#[derive(Clone, Default)]
+pub struct MemoryBus {
+    inboxes: Arc<Mutex<Vec<Sender<BeaconSignal>>>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus::default()
+    }
+
+    // A new transport that hears everything the other members of the bus send.
+    pub fn join(&self) -> MemoryTransport {
+        let (tx, rx) = channel::<BeaconSignal>();
+        let mut inboxes = self.inboxes.lock().unwrap_or_else(|e| e.into_inner());
+        inboxes.push(tx.clone());
+
+        MemoryTransport {
+            index: inboxes.len() - 1,
+            sender: tx,
+            inbox: Mutex::new(rx),
+            bus: self.clone(),
+        }
+    }
+}
+
+pub struct MemoryTransport {
+    index: usize,
+    sender: Sender<BeaconSignal>,
+    // Receivers can't be shared between threads on their own.
+    inbox: Mutex<Receiver<BeaconSignal>>,
+    bus: MemoryBus,
+}
+
+impl Default for MemoryTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryTransport {
+    // A transport on a bus of its own, which only hears what is put in its inbox.
+    pub fn new() -> MemoryTransport {
+        MemoryBus::new().join()
+    }
+
+    // Delivers straight to this transport, as if another member had sent it.
+    pub fn get_inbox(&self) -> Sender<BeaconSignal> {
+        self.sender.clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    // Members that have gone away are skipped rather than failing the send, the same as a radio would.
+    fn send(&self, signal: &BeaconSignal) -> Result<()> {
+        let inboxes = self.bus.inboxes.lock().unwrap_or_else(|e| e.into_inner());
+
+        for (i, inbox) in inboxes.iter().enumerate() {
+            if i != self.index {
+                let _ = inbox.send(signal.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<BeaconSignal>> {
+        let inbox = self.inbox.lock().unwrap_or_else(|e| e.into_inner());
+
+        match inbox.recv_timeout(timeout) {
+            Ok(signal) => Ok(Some(signal)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::ChannelClosed),
+        }
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+}
+
+impl UdpTransport {
+    // Binds to local and sends every signal to each of peers, e.g. one port per beacon on 127.0.0.1.
+    pub fn bind(local: impl ToSocketAddrs, peers: Vec<SocketAddr>) -> Result<UdpTransport> {
+        let socket = UdpSocket::bind(local).map_err(io_error)?;
+
+        Ok(UdpTransport { socket, peers })
+    }
+
+    // Joins a multicast group on the loopback interface, so any number of beacon processes on one box hear each other on a single port.  The port is shared with SO_REUSEADDR, and since multicast loops back, a beacon also hears its own signals.
+    pub fn multicast(group: Ipv4Addr, port: u16) -> Result<UdpTransport> {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).map_err(io_error)?;
+        socket.set_reuse_address(true).map_err(io_error)?;
+        // Without this the kernel sends on whichever interface the default route uses.
+        socket.set_multicast_if_v4(&Ipv4Addr::LOCALHOST).map_err(io_error)?;
+        socket.bind(&SocketAddr::from(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).into()).map_err(io_error)?;
+
+        let socket = UdpSocket::from(socket);
+        socket.join_multicast_v4(&group, &Ipv4Addr::LOCALHOST).map_err(io_error)?;
+        socket.set_multicast_loop_v4(true).map_err(io_error)?;
+        socket.set_multicast_ttl_v4(0).map_err(io_error)?;
+
+        Ok(UdpTransport { socket, peers: vec![SocketAddrV4::new(group, port).into()] })
+    }
+
+    pub fn get_local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr().map_err(io_error)
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&self, signal: &BeaconSignal) -> Result<()> {
+        let frame = signal.encode();
+
+        for peer in &self.peers {
+            self.socket.send_to(&frame, peer).map_err(io_error)?;
+        }
+
+        Ok(())
+    }
+
+    // A datagram that isn't a valid frame comes back as a Parse error, so the caller can log it and keep listening.
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<BeaconSignal>> {
+        // A zero timeout would mean block forever.
+        self.socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1)))).map_err(io_error)?;
+
+        let mut buffer = [0u8; MAX_DATAGRAM];
+
+        match self.socket.recv_from(&mut buffer) {
+            Ok((length, _)) => BeaconSignal::decode(&buffer[..length]).map(Some),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn get_signal(id: &str, distance: f64) -> BeaconSignal {
+        BeaconSignal { id: id.into(), distance, timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(5_000) }
+    }
+
+    #[test]
+    fn memory_bus_reaches_everyone_but_the_sender() {
+        let bus = MemoryBus::new();
+        let (a, b, c) = (bus.join(), bus.join(), bus.join());
+
+        a.send(&get_signal("A", 1.0)).unwrap();
+
+        assert_eq!(b.recv_timeout(Duration::from_millis(100)).unwrap().unwrap().id.as_ref(), "A");
+        assert_eq!(c.recv_timeout(Duration::from_millis(100)).unwrap().unwrap().id.as_ref(), "A");
+        assert!(a.recv_timeout(Duration::from_millis(10)).unwrap().is_none());
+    }
+
+    #[test]
+    fn udp_carries_frames_between_sockets() {
+        let a = UdpTransport::bind("127.0.0.1:0", vec![]).unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0", vec![a.get_local_addr().unwrap()]).unwrap();
+
+        b.send(&get_signal("B", 2.5)).unwrap();
+        let received = a.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!((received.id.as_ref(), received.distance), ("B", 2.5));
+
+        b.socket.send_to(b"not a frame", a.get_local_addr().unwrap()).unwrap();
+        assert!(matches!(a.recv_timeout(Duration::from_secs(1)), Err(Error::Parse(_))));
+        assert!(matches!(a.recv_timeout(Duration::from_millis(10)), Ok(None)));
+    }
+
+    // Reads until nothing arrives for a while and returns who was heard.
+    fn get_heard(transport: &UdpTransport) -> Vec<String> {
+        let mut heard = Vec::new();
+
+        while let Ok(Some(signal)) = transport.recv_timeout(Duration::from_millis(200)) {
+            heard.push(signal.id.to_string());
+        }
+
+        heard.sort();
+        heard.dedup();
+        heard
+    }
+
+    #[test]
+    fn multicast_peers_share_a_port() {
+        let group = Ipv4Addr::new(239, 255, 0, 1);
+        let port = 47_613;
+
+        // Sandboxes and some CI hosts have no multicast on loopback; there's nothing to test there.
+        let (a, b) = match (UdpTransport::multicast(group, port), UdpTransport::multicast(group, port)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (a, b) => {
+                eprintln!("skipping, can't join {}:{}: {:?}", group, port, a.err().or(b.err()));
+                return;
+            }
+        };
+
+        if a.send(&get_signal("A", 1.0)).is_err() || b.send(&get_signal("B", 2.0)).is_err() {
+            eprintln!("skipping, can't send to {}:{}", group, port);
+            return;
+        }
+
+        let (heard_a, heard_b) = (get_heard(&a), get_heard(&b));
+
+        if heard_a.is_empty() && heard_b.is_empty() {
+            eprintln!("skipping, multicast doesn't loop back here");
+            return;
+        }
+
+        // Both sockets hold the port, and each hears its own signal as well as the other's.
+        assert_eq!(heard_a, ["A", "B"]);
+        assert_eq!(heard_b, ["A", "B"]);
+    }
+}
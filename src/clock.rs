@@ -0,0 +1,81 @@
+// Where the time comes from.  Anything that ages readings or moves agents asks a
+// Clock instead of calling SystemTime::now() itself, so the same code runs against
+// the wall clock on hardware and against a simulated clock that only moves when a
+// simulation tells it to.
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> SystemTime;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+// A clock that stands still until it is advanced.  Clones share the same time, so one handle can drive every agent and beacon in a simulation.
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedClock {
+    // Nanoseconds since the Unix epoch, which covers until the year 2554.
+    nanos: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    pub fn new(start: SystemTime) -> SimulatedClock {
+        let clock = SimulatedClock::default();
+        clock.set(start);
+        clock
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::AcqRel);
+    }
+
+    // Moves the clock to time.  Time only goes forward, so an earlier time does nothing.
+    pub fn set(&self, time: SystemTime) {
+        let nanos = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        self.nanos.fetch_max(nanos, Ordering::AcqRel);
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.nanos.load(Ordering::Acquire))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock_is_shared_and_monotonic() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let clock = SimulatedClock::new(start);
+        let shared = clock.shared();
+
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(shared.now(), start + Duration::from_millis(1_500));
+
+        clock.set(start);
+        assert_eq!(shared.now(), start + Duration::from_millis(1_500));
+    }
+}
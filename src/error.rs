@@ -0,0 +1,46 @@
+// Everything the library can fail with.  Bad input off the radio or a graph that
+// can't be solved should come back to the caller as one of these, never as a panic
+// that takes the whole node down.
+
+use crate::identity::Identity;
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // A packet or stored record that couldn't be read.  Holds what was wrong with it.
+    Parse(String),
+    // The identity isn't known to the structure it was looked up in.
+    UnknownIdentity(Identity),
+    // Not enough nodes or measurements to do what was asked.
+    InsufficientData { needed: usize, found: usize },
+    // There is data, but its shape can't be solved, e.g. every node on top of the origin.
+    DegenerateGeometry(String),
+    // The other end of a channel has gone away, or it was never opened.
+    ChannelClosed,
+    // A worker thread panicked.  Holds the panic message when there was one.
+    WorkerPanicked(String),
+    // A worker thread didn't stop in time, and was left running detached.
+    Timeout(Identity),
+    // A socket or other OS level failure.  Holds the io::Error message, since io::Error can't be cloned or compared.
+    Io(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(reason) => write!(f, "parse error: {}", reason),
+            Error::UnknownIdentity(id) => write!(f, "unknown identity: {}", id),
+            Error::InsufficientData { needed, found } => write!(f, "insufficient data: needed {}, found {}", needed, found),
+            Error::DegenerateGeometry(reason) => write!(f, "degenerate geometry: {}", reason),
+            Error::ChannelClosed => write!(f, "channel closed"),
+            Error::WorkerPanicked(message) => write!(f, "worker panicked: {}", message),
+            Error::Timeout(id) => write!(f, "timed out waiting for {} to stop", id),
+            Error::Io(message) => write!(f, "io error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,4 +1,5 @@
 use std::cmp::{Ordering};
+use rand::{Rng, SeedableRng, rngs::StdRng};
 
 pub fn f64_ordering(x: &f64, y: &f64) -> Ordering {
     if x < y { 
@@ -9,81 +10,464 @@ pub fn f64_ordering(x: &f64, y: &f64) -> Ordering {
         return Ordering::Greater 
     } 
     
-    return Ordering::Equal;
+    Ordering::Equal
 }
 
-fn get_spread(v: &Vec<f64>) -> f64 {
-    // let v_iter = v.into_iter();
-    let min_val: f64 = {v.iter().cloned().min_by(f64_ordering).unwrap()};
-    let max_val: f64 = {v.iter().cloned().max_by(f64_ordering).unwrap()};
-    max_val - min_val
+// The result of reducing a set of distance samples down to a single distance.  `certainty` is the fraction of the samples (0.0 to 1.0) that agreed with the estimate, and `samples` is how many samples the filter was given.  An estimate built from no samples has a certainty of 0.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterEstimate {
+    pub value: f64,
+    pub certainty: f64,
+    pub samples: usize,
 }
 
-pub fn beam_filter(v: &Vec<f64>) -> f64 {
-    let spread = get_spread(v);
-    // [TODO] Create dynamic width based on variance within the given values.  This would basically be standard deviation.
-    let beam = spread * 0.2;
-    let mut best_fit: Vec<f64> = vec![];
+impl FilterEstimate {
+    pub fn empty() -> FilterEstimate {
+        FilterEstimate {
+            value: 0.0,
+            certainty: 0.0,
+            samples: 0,
+        }
+    }
 
-    for i in 0..v.len() {
-        let mut current_fit: Vec<f64> = Vec::<f64>::new();
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+    }
+}
 
-        for j in 0..v.len() {
-            if i == j {
-                continue;
-            }
+pub trait DistanceFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate;
+}
+
+// None when there are no samples.
+fn get_spread(v: &[f64]) -> Option<f64> {
+    let min_val = v.iter().cloned().min_by(f64_ordering)?;
+    let max_val = v.iter().cloned().max_by(f64_ordering)?;
+
+    Some(max_val - min_val)
+}
+
+// Keeps the largest group of samples that sit within a beam of each other, where the beam is a fraction of the total spread of the samples.
+#[derive(Clone, Debug)]
+pub struct BeamFilter {
+    pub width: f64,
+}
+
+impl Default for BeamFilter {
+    fn default() -> Self {
+        BeamFilter {
+            width: 0.2,
+        }
+    }
+}
+
+impl DistanceFilter for BeamFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        let spread = match get_spread(v) {
+            Some(spread) => spread,
+            None => return FilterEstimate::empty(),
+        };
+
+        if v.len() == 1 {
+            return FilterEstimate { value: v[0], certainty: 1.0, samples: 1 };
+        }
+
+        // [TODO] Create dynamic width based on variance within the given values.  This would basically be standard deviation.
+        let beam = spread * self.width;
+        let mut best_fit: Vec<f64> = vec![];
+
+        for i in 0..v.len() {
+            let mut current_fit: Vec<f64> = Vec::<f64>::new();
 
-            let dist = v[j] - v[i];
+            for j in 0..v.len() {
+                if i == j {
+                    continue;
+                }
 
-            if dist * dist <= beam {
-                current_fit.push(v[j]);
+                let dist = v[j] - v[i];
+
+                if dist * dist <= beam {
+                    current_fit.push(v[j]);
+                }
+            }
+
+            if current_fit.len() > best_fit.len() {
+                best_fit = current_fit;
             }
         }
 
-        if current_fit.len() > best_fit.len() {
-            best_fit = current_fit;
+        // Nothing landed in the beam, so every sample is as good as any other.
+        if best_fit.is_empty() {
+            best_fit = v.to_vec();
+        }
+
+        FilterEstimate {
+            value: best_fit.iter().sum::<f64>() / best_fit.len() as f64,
+            certainty: best_fit.len() as f64 / v.len() as f64,
+            samples: v.len(),
         }
     }
-    
-    let output = best_fit.iter().sum::<f64>() / best_fit.len() as f64;
-    let certainty = best_fit.len() as f64 / v.len() as f64;
+}
 
-    // println!("input: {:?}, output: {:?}, len diff: {:?}, avg: {:?}", v, best_fit, v.len() - best_fit.len(), output);
-    // println!("len diff: {:?}, avg: {:?}, certainty: {:?}", v.len() - best_fit.len(), output, certainty);
-    
-    output
+// Sorts the samples and averages a window around the median.  `deviation` is the fraction of the samples taken on either side of the median.
+#[derive(Clone, Debug)]
+pub struct BeamDeviationFilter {
+    pub deviation: f64,
 }
 
-pub fn beam_deviation_filter(v: &Vec<f64>) -> f64 {
-    if v.len() == 0 {
+impl Default for BeamDeviationFilter {
+    fn default() -> Self {
+        BeamDeviationFilter {
+            deviation: 0.1,
+        }
+    }
+}
+
+impl DistanceFilter for BeamDeviationFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
+
+        if v.len() == 1 {
+            return FilterEstimate { value: v[0], certainty: 1.0, samples: 1 };
+        }
+
+        let mut working_v = v.to_vec();
+        working_v.sort_by(f64_ordering);
+
+        let mid = working_v.len() / 2;
+        let v_dev = ((working_v.len() as f64 * self.deviation) as usize).min(mid);
+        let start = mid - v_dev;
+        let end = (mid + v_dev).min(working_v.len() - 1);
+
+        // [TODO] Create dynamic width based on variance within the given values.  This would basically be standard deviation.
+        let best_fit = &working_v[start..(end + 1)];
+
+        FilterEstimate {
+            value: best_fit.iter().sum::<f64>() / best_fit.len() as f64,
+            certainty: best_fit.len() as f64 / v.len() as f64,
+            samples: v.len(),
+        }
+    }
+}
+
+pub fn beam_filter(v: &[f64]) -> f64 {
+    BeamFilter::default().estimate(v).value
+}
+
+pub fn beam_deviation_filter(v: &[f64]) -> f64 {
+    BeamDeviationFilter::default().estimate(v).value
+}
+
+fn get_sorted(v: &[f64]) -> Vec<f64> {
+    let mut sorted = v.to_vec();
+    sorted.sort_by(f64_ordering);
+    sorted
+}
+
+// Expects the values to already be sorted.
+fn get_median(sorted: &[f64]) -> f64 {
+    if sorted.is_empty() {
         return 0.0;
     }
 
-    if v.len() == 1 {
-        return v[0];
+    let mid = sorted.len() / 2;
+
+    if sorted.len().is_multiple_of(2) {
+        return (sorted[mid - 1] + sorted[mid]) / 2.0;
+    }
+
+    sorted[mid]
+}
+
+// Median absolute deviation scaled by 1.4826 so it lines up with the standard deviation of normally distributed samples.
+fn get_scaled_mad(v: &[f64], median: f64) -> f64 {
+    let deviations: Vec<f64> = v.iter().map(|x| (x - median).abs()).collect();
+    get_median(&get_sorted(&deviations)) * 1.4826
+}
+
+fn get_mean(v: &[f64]) -> f64 {
+    v.iter().sum::<f64>() / v.len() as f64
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MedianFilter;
+
+impl DistanceFilter for MedianFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
+
+        let sorted = get_sorted(v);
+        let median = get_median(&sorted);
+        let mad = get_scaled_mad(v, median);
+
+        // The median always uses every sample, so certainty is the share of samples that are within one scaled MAD of it.
+        let agreeing = v.iter().filter(|x| (*x - median).abs() <= mad).count();
+
+        FilterEstimate {
+            value: median,
+            certainty: agreeing as f64 / v.len() as f64,
+            samples: v.len(),
+        }
+    }
+}
+
+// Drops every sample further than `threshold` scaled MADs from the median and averages what is left.
+#[derive(Clone, Debug)]
+pub struct MadFilter {
+    pub threshold: f64,
+}
+
+impl Default for MadFilter {
+    fn default() -> Self {
+        MadFilter {
+            threshold: 3.0,
+        }
+    }
+}
+
+impl DistanceFilter for MadFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
+
+        let median = get_median(&get_sorted(v));
+        let mad = get_scaled_mad(v, median);
+
+        // With more than half the samples identical the MAD is 0.0, so only exact matches to the median survive.
+        let inliers: Vec<f64> = v.iter().cloned().filter(|x| (x - median).abs() <= self.threshold * mad).collect();
+
+        FilterEstimate {
+            value: get_mean(&inliers),
+            certainty: inliers.len() as f64 / v.len() as f64,
+            samples: v.len(),
+        }
+    }
+}
+
+// Hampel identifier.  Samples are treated as a time ordered series, and each sample is compared to the median of the `window` samples on either side of it.  Outliers are replaced with that local median instead of being dropped, and the cleaned series is averaged.
+#[derive(Clone, Debug)]
+pub struct HampelFilter {
+    pub window: usize,
+    pub threshold: f64,
+}
+
+impl Default for HampelFilter {
+    fn default() -> Self {
+        HampelFilter {
+            window: 3,
+            threshold: 3.0,
+        }
     }
+}
+
+impl DistanceFilter for HampelFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
 
-    let mut working_v = v.clone();
-    working_v.sort_by(f64_ordering );
+        let mut cleaned: Vec<f64> = Vec::with_capacity(v.len());
+        let mut replaced = 0;
 
-    let v_dev = (working_v.len() as f64 * 0.1) as usize;
-    let mid = working_v.len() / 2 as usize;
-    let start = mid - v_dev;
-    let end = mid + v_dev;
+        for i in 0..v.len() {
+            let start = i.saturating_sub(self.window);
+            let end = (i + self.window + 1).min(v.len());
+            let neighborhood = &v[start..end];
 
-    // [TODO] Create dynamic width based on variance within the given values.  This would basically be standard deviation.
-    let mut best_fit: Vec<f64> = vec![];
+            let median = get_median(&get_sorted(neighborhood));
+            let mad = get_scaled_mad(neighborhood, median);
 
-    for i in start..(end + 1) {
-        for j in start..(end + 1) {
-            if i == j {
-                continue;
+            if (v[i] - median).abs() > self.threshold * mad {
+                cleaned.push(median);
+                replaced += 1;
+            } else {
+                cleaned.push(v[i]);
             }
-            best_fit.push(working_v[j]);
+        }
+
+        FilterEstimate {
+            value: get_mean(&cleaned),
+            certainty: (v.len() - replaced) as f64 / v.len() as f64,
+            samples: v.len(),
         }
     }
-    
-    let output = best_fit.iter().sum::<f64>() / best_fit.len() as f64;
-    
-    output
-}
\ No newline at end of file
+}
+
+// Averages the samples after dropping `proportion` of them from each end of the sorted samples.
+#[derive(Clone, Debug)]
+pub struct TrimmedMeanFilter {
+    pub proportion: f64,
+}
+
+impl Default for TrimmedMeanFilter {
+    fn default() -> Self {
+        TrimmedMeanFilter {
+            proportion: 0.1,
+        }
+    }
+}
+
+impl DistanceFilter for TrimmedMeanFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
+
+        let sorted = get_sorted(v);
+        let trim = ((sorted.len() as f64 * self.proportion.clamp(0.0, 0.5)) as usize).min((sorted.len() - 1) / 2);
+        let kept = &sorted[trim..(sorted.len() - trim)];
+
+        FilterEstimate {
+            value: get_mean(kept),
+            certainty: kept.len() as f64 / v.len() as f64,
+            samples: v.len(),
+        }
+    }
+}
+
+// Random sample consensus.  Each iteration picks a sample as the candidate distance and counts the samples within `inlier_threshold` of it, keeping the candidate with the most inliers.  The estimate is the mean of that consensus set.  When there are no more samples than iterations every sample is tried, so small sets are deterministic regardless of the seed.
+#[derive(Clone, Debug)]
+pub struct RansacFilter {
+    pub iterations: usize,
+    pub inlier_threshold: f64,
+    pub seed: u64,
+}
+
+impl Default for RansacFilter {
+    fn default() -> Self {
+        RansacFilter {
+            iterations: 50,
+            inlier_threshold: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+impl RansacFilter {
+    fn get_inliers(&self, v: &[f64], candidate: f64) -> Vec<f64> {
+        v.iter().cloned().filter(|x| (x - candidate).abs() <= self.inlier_threshold).collect()
+    }
+}
+
+impl DistanceFilter for RansacFilter {
+    fn estimate(&self, v: &[f64]) -> FilterEstimate {
+        if v.is_empty() {
+            return FilterEstimate::empty();
+        }
+
+        let candidates: Vec<f64> = if v.len() <= self.iterations {
+            v.to_vec()
+        } else {
+            let mut rng = StdRng::seed_from_u64(self.seed);
+            (0..self.iterations).map(|_| v[rng.gen_range(0..v.len())]).collect()
+        };
+
+        let mut best_fit: Vec<f64> = vec![];
+
+        for candidate in candidates {
+            let inliers = self.get_inliers(v, candidate);
+
+            if inliers.len() > best_fit.len() {
+                best_fit = inliers;
+            }
+        }
+
+        FilterEstimate {
+            value: get_mean(&best_fit),
+            certainty: best_fit.len() as f64 / v.len() as f64,
+            samples: v.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ranges around 10.0 with a couple of multipath reflections.
+    fn get_samples() -> Vec<f64> {
+        vec![10.1, 9.9, 10.0, 10.2, 35.0, 9.8, 10.0, 42.0, 10.1, 9.9]
+    }
+
+    fn assert_near(estimate: FilterEstimate, expected: f64) {
+        assert!((estimate.value - expected).abs() < 0.15, "{:?} was not near {}", estimate, expected);
+    }
+
+    #[test]
+    fn robust_filters_reject_outliers() {
+        let samples = get_samples();
+
+        assert_near(MedianFilter.estimate(&samples), 10.0);
+        assert_near(MadFilter::default().estimate(&samples), 10.0);
+        assert_near(HampelFilter::default().estimate(&samples), 10.0);
+        assert_near(TrimmedMeanFilter { proportion: 0.2 }.estimate(&samples), 10.0);
+        assert_near(RansacFilter::default().estimate(&samples), 10.0);
+    }
+
+    #[test]
+    fn certainty_reflects_outliers() {
+        let samples = get_samples();
+
+        assert_eq!(MadFilter::default().estimate(&samples).certainty, 0.8);
+        assert_eq!(RansacFilter::default().estimate(&samples).certainty, 0.8);
+    }
+
+    // beam_filter as it was before it became a DistanceFilter, returning the certainty it computed and then threw away.
+    fn get_legacy_beam(v: &[f64]) -> (f64, f64) {
+        let spread = v.iter().cloned().fold(f64::MIN, f64::max) - v.iter().cloned().fold(f64::MAX, f64::min);
+        let beam = spread * 0.2;
+        let mut best_fit: Vec<f64> = vec![];
+
+        for i in 0..v.len() {
+            let current_fit: Vec<f64> = (0..v.len()).filter(|j| *j != i && (v[*j] - v[i]).powi(2) <= beam).map(|j| v[j]).collect();
+
+            if current_fit.len() > best_fit.len() {
+                best_fit = current_fit;
+            }
+        }
+
+        (best_fit.iter().sum::<f64>() / best_fit.len() as f64, best_fit.len() as f64 / v.len() as f64)
+    }
+
+    #[test]
+    fn beam_filters_match_legacy_output() {
+        for samples in [get_samples(), vec![4.0, 4.2, 3.9, 8.0, 8.1], vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]] {
+            let (value, certainty) = get_legacy_beam(&samples);
+            let estimate = BeamFilter::default().estimate(&samples);
+            assert!((estimate.value - value).abs() < 1e-12 && estimate.certainty == certainty, "{:?} from {:?}", estimate, samples);
+            assert_eq!(beam_filter(&samples), estimate.value);
+        }
+
+        // Outliers lower the certainty instead of being averaged in.
+        let estimate = BeamFilter::default().estimate(&get_samples());
+        assert!((estimate.value - 10.0).abs() < 0.05 && estimate.certainty == 0.7, "{:?}", estimate);
+
+        // The deviation filter averages the sample above the middle and one either side of it, 10.0, 10.1 and 10.1 here, so 3 of the 10.
+        let estimate = BeamDeviationFilter::default().estimate(&get_samples());
+        assert!((estimate.value - 30.2 / 3.0).abs() < 1e-12 && estimate.certainty == 0.3, "{:?}", estimate);
+        assert_eq!(beam_deviation_filter(&get_samples()), estimate.value);
+    }
+
+    #[test]
+    fn filters_handle_empty_input() {
+        let filters: Vec<Box<dyn DistanceFilter>> = vec![
+            Box::new(BeamFilter::default()),
+            Box::new(BeamDeviationFilter::default()),
+            Box::new(MedianFilter),
+            Box::new(MadFilter::default()),
+            Box::new(HampelFilter::default()),
+            Box::new(TrimmedMeanFilter::default()),
+            Box::new(RansacFilter::default()),
+        ];
+
+        for filter in filters {
+            assert!(filter.estimate(&[]).is_empty());
+            assert_eq!(filter.estimate(&[4.0]).value, 4.0);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// Pre-solve checks on the shape of a distance graph.  Ranges alone can only pin a
+// node down in the plane when it has ranges to at least three nodes that are not on
+// one line and that are themselves ranged to each other.  Anything short of that
+// either has a mirror image that fits just as well (ambiguous) or sits somewhere on
+// a circle (unlocalizable), and the solvers should say so rather than guess.
+
+use crate::filter::FilterEstimate;
+use crate::identity::Identity;
+
+// Independent ranges needed to pin a node down in the plane.
+const MIN_INDEPENDENT_RANGES: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct GeometryConfig {
+    // Nodes measured closer together than this are treated as the same point.
+    pub coincident_tolerance: f64,
+    // Three nodes are collinear when the height of their triangle is less than this fraction of its longest side.
+    pub collinear_tolerance: f64,
+}
+
+impl Default for GeometryConfig {
+    fn default() -> Self {
+        GeometryConfig {
+            coincident_tolerance: 1e-3,
+            collinear_tolerance: 0.01,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeometryIssue {
+    // Sits on top of another node, so its ranges say nothing the other node's don't.
+    Coincident(Identity),
+    // Every set of three nodes it is ranged to lies on a line, so it can be mirrored across that line.
+    Collinear,
+    // Number of ranges to nodes that are usable together, when that is fewer than three.
+    InsufficientRanges(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeGeometry {
+    WellDetermined,
+    // Can be placed, but there is more than one place that fits.
+    Ambiguous(GeometryIssue),
+    // Can't be placed at all.
+    Unlocalizable(GeometryIssue),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GeometryCheck {
+    // In the same order as the nodes that were checked.
+    pub nodes: Vec<(Identity, NodeGeometry)>,
+}
+
+impl GeometryCheck {
+    pub fn get(&self, id: &Identity) -> Option<&NodeGeometry> {
+        self.nodes.iter().find(|(node, _)| node == id).map(|(_, geometry)| geometry)
+    }
+
+    fn get_matching(&self, matches: fn(&NodeGeometry) -> bool) -> Vec<Identity> {
+        self.nodes.iter().filter(|(_, geometry)| matches(geometry)).map(|(id, _)| id.clone()).collect()
+    }
+
+    pub fn get_well_determined(&self) -> Vec<Identity> {
+        self.get_matching(|g| *g == NodeGeometry::WellDetermined)
+    }
+
+    pub fn get_ambiguous(&self) -> Vec<Identity> {
+        self.get_matching(|g| matches!(g, NodeGeometry::Ambiguous(_)))
+    }
+
+    pub fn get_unlocalizable(&self) -> Vec<Identity> {
+        self.get_matching(|g| matches!(g, NodeGeometry::Unlocalizable(_)))
+    }
+
+    pub fn is_degenerate(&self) -> bool {
+        self.nodes.iter().any(|(_, geometry)| *geometry != NodeGeometry::WellDetermined)
+    }
+}
+
+fn is_collinear(a: f64, b: f64, c: f64, tolerance: f64) -> bool {
+    let longest = a.max(b).max(c);
+
+    if longest <= 0.0 {
+        return true;
+    }
+
+    // Heron's formula, clamped since noisy ranges can break the triangle inequality.
+    let s = (a + b + c) / 2.0;
+    let area = (s * (s - a) * (s - b) * (s - c)).max(0.0).sqrt();
+
+    2.0 * area / longest < tolerance * longest
+}
+
+// Classifies every node from a symmetric matrix of filtered ranges in the order of references, the same matrix DistanceGraph::get_filtered_distances returns.  Empty estimates are pairs that were never measured, while a measured 0.0 is a coincident pair.
+pub fn check_geometry(references: &[Identity], estimates: &[Vec<FilterEstimate>], config: &GeometryConfig) -> GeometryCheck {
+    let is_measured = |i: usize, j: usize| !estimates[i][j].is_empty();
+    let is_coincident = |i: usize, j: usize| is_measured(i, j) && estimates[i][j].value <= config.coincident_tolerance;
+
+    let mut nodes = vec![];
+
+    for i in 0..references.len() {
+        // Only the first of a coincident pair keeps its ranges, the other is reported against it.
+        if let Some(j) = (0..i).find(|j| is_coincident(i, *j)) {
+            nodes.push((references[i].clone(), NodeGeometry::Ambiguous(GeometryIssue::Coincident(references[j].clone()))));
+            continue;
+        }
+
+        let neighbours: Vec<usize> = (0..references.len())
+            .filter(|j| *j != i && is_measured(i, *j) && !is_coincident(i, *j))
+            .collect();
+
+        let mut any_triangle = false;
+        let mut independent = neighbours.len().min(2);
+
+        'search: for (x, a) in neighbours.iter().enumerate() {
+            for (y, b) in neighbours.iter().enumerate().skip(x + 1) {
+                for c in neighbours.iter().skip(y + 1) {
+                    if !(is_measured(*a, *b) && is_measured(*a, *c) && is_measured(*b, *c)) {
+                        continue;
+                    }
+
+                    any_triangle = true;
+
+                    if !is_collinear(estimates[*a][*b].value, estimates[*a][*c].value, estimates[*b][*c].value, config.collinear_tolerance) {
+                        independent = MIN_INDEPENDENT_RANGES;
+                        break 'search;
+                    }
+                }
+            }
+        }
+
+        let geometry = if independent >= MIN_INDEPENDENT_RANGES {
+            NodeGeometry::WellDetermined
+        } else if neighbours.len() < 2 {
+            NodeGeometry::Unlocalizable(GeometryIssue::InsufficientRanges(neighbours.len()))
+        } else if any_triangle {
+            NodeGeometry::Ambiguous(GeometryIssue::Collinear)
+        } else {
+            NodeGeometry::Ambiguous(GeometryIssue::InsufficientRanges(independent))
+        };
+
+        nodes.push((references[i].clone(), geometry));
+    }
+
+    GeometryCheck { nodes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::filter::MedianFilter;
+    use crate::location::{DistanceGraph, MomentEdge};
+    use crate::polar::PolarCoordinates;
+    use std::time::SystemTime;
+
+    fn get_graph(layout: &[(&str, f64, f64)], skip: &[(&str, &str)]) -> DistanceGraph {
+        let mut graph = DistanceGraph::new();
+
+        for (i, (a, ax, ay)) in layout.iter().enumerate() {
+            for (b, bx, by) in &layout[i + 1..] {
+                if skip.contains(&(*a, *b)) {
+                    continue;
+                }
+
+                let distance = ((ax - bx).powi(2) + (ay - by).powi(2)).sqrt();
+                graph.add(MomentEdge::new((*a).into(), (*b).into(), distance, SystemTime::now()));
+            }
+        }
+
+        graph
+    }
+
+    #[test]
+    fn classifies_degenerate_nodes() {
+        let layout = [("A", 0.0, 0.0), ("B", 10.0, 0.0), ("C", 20.0, 0.0), ("D", 5.0, 5.0), ("E", 10.0, 0.0), ("F", -5.0, 0.0)];
+        // F only ever ranged to A, and D only to the three nodes on the x axis.
+        let skip = [("B", "F"), ("C", "F"), ("D", "F"), ("E", "F"), ("D", "E")];
+        let graph = get_graph(&layout, &skip);
+        let nodes: Vec<Identity> = layout.iter().map(|(id, _, _)| (*id).into()).collect();
+
+        let check = graph.check_geometry(&nodes, &MedianFilter, &GeometryConfig::default());
+
+        assert_eq!(check.get(&"A".into()), Some(&NodeGeometry::WellDetermined));
+        assert_eq!(check.get(&"D".into()), Some(&NodeGeometry::Ambiguous(GeometryIssue::Collinear)));
+        assert_eq!(check.get(&"E".into()), Some(&NodeGeometry::Ambiguous(GeometryIssue::Coincident("B".into()))));
+        assert_eq!(check.get(&"F".into()), Some(&NodeGeometry::Unlocalizable(GeometryIssue::InsufficientRanges(1))));
+        assert!(check.is_degenerate());
+    }
+
+    #[test]
+    fn solver_reports_instead_of_panicking() {
+        // Every node on one line, the origin coincident with its first neighbour, and one node that never ranged to the origin.
+        let layout = [("A", 0.0, 0.0), ("B", 0.0, 0.0), ("C", 10.0, 0.0), ("D", 20.0, 0.0), ("E", 30.0, 0.0)];
+        let graph = get_graph(&layout, &[("A", "E"), ("B", "E")]);
+        let nodes: Vec<Identity> = layout.iter().map(|(id, _, _)| (*id).into()).collect();
+
+        let solution = graph.get_resolved_position_graph(&nodes, &MedianFilter, None).unwrap();
+        let coordinates = &solution.resolution.coordinates;
+
+        assert_eq!(solution.geometry.get_well_determined(), Vec::<Identity>::new());
+        assert!((coordinates[&"D".into()].radius - 20.0).abs() < 1e-9);
+        assert_eq!(coordinates[&"B".into()].radius, 0.0);
+        assert!(solution.unplaced.contains(&"E".into()));
+        assert!(solution.resolution.ambiguous.contains(&"D".into()));
+
+        let single = DistanceGraph::new().get_resolved_position_graph(&["A".into()], &MedianFilter, None);
+        assert_eq!(single.unwrap_err(), Error::InsufficientData { needed: 2, found: 1 });
+
+        let stacked = get_graph(&[("A", 0.0, 0.0), ("B", 0.0, 0.0)], &[]).get_resolved_position_graph(&["A".into(), "B".into()], &MedianFilter, None);
+        assert!(matches!(stacked, Err(Error::DegenerateGeometry(_))));
+
+        let short = PolarCoordinates::from_distances("A".into(), "B".into(), vec![], &[("A".into(), 0.0), ("B".into(), 1.0)], &[vec![0.0, 1.0]]);
+        assert_eq!(short.unwrap_err(), Error::InsufficientData { needed: 2, found: 1 });
+    }
+
+    #[test]
+    fn unlocalizable_nodes_are_left_unplaced() {
+        // F is first in line for calibration but only ever ranged to the origin, so nothing else could be triangulated off of it.
+        let layout = [("A", 0.0, 0.0), ("F", -5.0, 0.0), ("B", 10.0, 0.0), ("C", 0.0, 10.0), ("D", 10.0, 10.0)];
+        let graph = get_graph(&layout, &[("F", "B"), ("F", "C"), ("F", "D")]);
+        let nodes: Vec<Identity> = layout.iter().map(|(id, _, _)| (*id).into()).collect();
+
+        let solution = graph.get_resolved_position_graph(&nodes, &MedianFilter, None).unwrap();
+        let coordinates = &solution.resolution.coordinates;
+
+        assert_eq!(solution.unplaced, vec![Identity::from("F")]);
+        assert!(coordinates.get(&"F".into()).is_none());
+        assert!((coordinates[&"D".into()].radius - 200f64.sqrt()).abs() < 1e-9);
+        assert!(!solution.resolution.is_ambiguous());
+    }
+}
@@ -1,14 +1,49 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+// Relative navigation for swarms that can only range each other.  Everything is
+// built from pairwise distances: DistanceGraph collects them, the solvers turn them
+// into PolarCoordinates around one of the nodes, and Navigator / Beacon / Agent wrap
+// that up for nodes running in the field.
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub mod agent;
+pub mod agent_manager;
+pub mod beacon;
+pub mod clock;
+pub mod error;
+pub mod filter;
+pub mod geometry;
+pub mod identity;
+pub mod location;
+pub mod matrix;
+pub mod mds;
+pub mod mirror;
+pub mod multilateration;
+pub mod navigation;
+pub mod object_store;
+pub mod particle;
+pub mod point;
+pub mod polar;
+pub mod registration;
+pub mod signal;
+pub mod simulation;
+pub mod time_series;
+#[cfg(feature = "serde")]
+pub mod timestamp;
+pub mod tracking;
+pub mod uncertainty;
+pub mod wire;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
+// The types most callers need, so a consumer can start with `use navigation::prelude::*;`.  Only things expected to stay put across releases belong here.
+pub mod prelude {
+    pub use crate::agent::Agent;
+    pub use crate::agent_manager::AgentManager;
+    pub use crate::beacon::{Beacon, BeaconConfig, BeaconSignal, MemoryBus, MemoryTransport, Transport, UdpTransport};
+    pub use crate::clock::{Clock, SharedClock, SimulatedClock, SystemClock};
+    pub use crate::error::{Error, Result};
+    pub use crate::filter::{BeamDeviationFilter, BeamFilter, DistanceFilter, FilterEstimate, MedianFilter};
+    pub use crate::identity::Identity;
+    pub use crate::location::{DistanceGraph, Location, MomentEdge};
+    pub use crate::navigation::{Navigator, NodeLocation};
+    pub use crate::point::Point2;
+    pub use crate::polar::{Angle, Coordinate, PolarCoordinates, Radial, Radius};
+    pub use crate::signal::Signal;
+    pub use crate::time_series::LocationTimeSeries;
 }
@@ -1,16 +1,20 @@
-use datetime::LocalDateTime;
-use crate::{identity::Identity, filter::f64_ordering, polar::*};
-use crate::test_suite::*;
+use crate::{identity::Identity, filter::{DistanceFilter, FilterEstimate}, polar::*};
+use crate::mds::{self, MdsConfig, MdsSolution};
+use crate::error::{Error, Result};
+use crate::geometry::{self, GeometryCheck, GeometryConfig, GeometryIssue, NodeGeometry};
+use crate::mirror::{self, HandednessHint, MirrorConfig, MirrorResolution};
 use std::{sync::Arc, collections::HashMap};
 use std::time::SystemTime;
 
 pub type DistanceVector = Arc<[MomentEdge]>;
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MomentEdge {
     pub left: Identity,
     pub right: Identity,
     pub distance: f64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timestamp::millis"))]
     pub timestamp: SystemTime
 }
 
@@ -19,12 +23,13 @@ impl MomentEdge {
         MomentEdge {
             left: left.clone(),
             right: right.clone(),
-            distance: distance,
-            timestamp: timestamp
+            distance,
+            timestamp
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Edge {
     pub left: Identity,
     pub right: Identity,
@@ -36,15 +41,16 @@ impl Edge {
         Edge {
             left: left.clone(),
             right: right.clone(),
-            distance: distance,
+            distance,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Location {
-    node: Identity,
-    distances: DistanceVector
+    pub node: Identity,
+    pub distances: DistanceVector
 }
 
 impl Location {
@@ -70,13 +76,21 @@ impl Location {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DistanceGraph {
     pub lefts: Vec<Identity>,
     pub rights: Vec<Identity>,
     pub distances: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::timestamp::millis_vec"))]
     pub timestamps: Vec<SystemTime>,
 }
 
+impl Default for DistanceGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DistanceGraph {
     pub fn new() -> DistanceGraph {
         DistanceGraph {
@@ -118,6 +132,10 @@ impl DistanceGraph {
         MomentEdge { left: self.lefts[idx].clone(), right: self.rights[idx].clone(), distance: self.distances[idx], timestamp: self.timestamps[idx] }
     }
 
+    pub fn edges(&self) -> Vec<MomentEdge> {
+        (0..self.lefts.len()).map(|i| self.get_idx(i)).collect()
+    }
+
     pub fn get_node_graph(&self, node: Identity) -> DistanceGraph {
         let mut dg = DistanceGraph::new();
 
@@ -127,34 +145,82 @@ impl DistanceGraph {
             }
         }
 
-        return dg;
+        dg
     }
 
-    pub fn get_position_graph(&self, nodes: &Vec<Identity>, filter: &dyn Fn(&Vec<f64>) -> f64) -> PolarCoordinates {
+    pub fn get_position_graph(&self, nodes: &[Identity], filter: &dyn DistanceFilter) -> Result<PolarCoordinates> {
+        Ok(self.get_resolved_position_graph(nodes, filter, None)?.resolution.coordinates)
+    }
+
+    // Same solve as get_position_graph, but also reports the geometry of every node, the nodes that couldn't be put on a side of the calibration line, and the nodes that couldn't be placed at all.  Takes an optional hint to pin the handedness of the frame.
+    pub fn get_resolved_position_graph(&self, nodes: &[Identity], filter: &dyn DistanceFilter, hint: Option<&HandednessHint>) -> Result<PositionSolution> {
         // Makes the assumption the data given for the time bin of the beacons within the graph are "non-moving" and "reliable."
         
         // [TODO] Make calculations based on the fact that some beacons may be "unreliable."
         // [TODO] Make calculations based on the fact that beacons move basaed on the reference frame of other beacons over the time window given.
-        let mut cleaned_nodes = nodes.clone();
+        let (cleaned_nodes, estimates) = self.get_filtered_distances(nodes, filter);
+        let geometry = geometry::check_geometry(&cleaned_nodes, &estimates, &GeometryConfig::default());
+
+        let (resolution, unplaced) = self.get_coordinates(&cleaned_nodes, &estimates, &geometry, hint)?;
+
+        Ok(PositionSolution {
+            resolution,
+            geometry,
+            unplaced,
+        })
+    }
+
+    // Classifies every node as well-determined, ambiguous or unlocalizable from the filtered ranges between them, before any solve.
+    pub fn check_geometry(&self, nodes: &[Identity], filter: &dyn DistanceFilter, config: &GeometryConfig) -> GeometryCheck {
+        let (cleaned_nodes, estimates) = self.get_filtered_distances(nodes, filter);
+        geometry::check_geometry(&cleaned_nodes, &estimates, config)
+    }
+
+    // Solves the whole graph at once with multidimensional scaling rather than triangulating off of the first two nodes.  Each pair is weighted by the filter's certainty, and pairs without any measurements are left out of the fit.
+    pub fn get_mds_position_graph(&self, nodes: &[Identity], filter: &dyn DistanceFilter, config: &MdsConfig) -> Result<PolarCoordinates> {
+        let (references, solution) = self.get_mds_solution(nodes, filter, 2, config)?;
+        mds::to_polar_coordinates(&references, &solution.points)
+    }
+
+    // The 3D solver path.  Ranges don't care how many dimensions the swarm is spread over, so this is the same fit as get_mds_position_graph with a third axis.
+    pub fn get_spherical_position_graph(&self, nodes: &[Identity], filter: &dyn DistanceFilter, config: &MdsConfig) -> Result<SphericalCoordinates> {
+        let (references, solution) = self.get_mds_solution(nodes, filter, 3, config)?;
+        mds::to_spherical_coordinates(&references, &solution.points)
+    }
+
+    fn get_mds_solution(&self, nodes: &[Identity], filter: &dyn DistanceFilter, dimensions: usize, config: &MdsConfig) -> Result<(Vec<Identity>, MdsSolution)> {
+        let (cleaned_nodes, estimates) = self.get_filtered_distances(nodes, filter);
+
+        if cleaned_nodes.is_empty() {
+            return Err(Error::InsufficientData { needed: 1, found: 0 });
+        }
+
+        let distances: Vec<Vec<f64>> = estimates.iter().map(|row| row.iter().map(|e| e.value).collect()).collect();
+        let weights: Vec<Vec<f64>> = estimates.iter().map(|row| row.iter().map(|e| if e.is_empty() { 0.0 } else { e.certainty.max(1e-3) }).collect()).collect();
+
+        let solution = mds::solve(&distances, &weights, dimensions, config);
+
+        Ok((cleaned_nodes, solution))
+    }
+
+    // Runs the filter over every pair of the given nodes, returning the deduplicated node order and a symmetric matrix of estimates in that order.  Pairs with no samples (and the diagonal) are left as empty estimates.
+    pub fn get_filtered_distances(&self, nodes: &[Identity], filter: &dyn DistanceFilter) -> (Vec<Identity>, Vec<Vec<FilterEstimate>>) {
+        let mut cleaned_nodes = nodes.to_vec();
         cleaned_nodes.dedup();
         let node_reference: HashMap<Identity, usize> = cleaned_nodes.iter().enumerate().map(|value| (value.1.clone(), value.0)).collect::<HashMap<Identity, usize>>();
         let mut distance_vec = vec![vec![Vec::<f64>::new(); cleaned_nodes.len()]; cleaned_nodes.len()];
 
-        let mut beacon_map = HashMap::<(Identity, Identity), (f64, LocalDateTime)>::new();
-
-        let start = SystemTime::now();
-
         for i in 0..self.lefts.len() {
             let left = &self.lefts[i];
             let right = &self.rights[i];
 
-            if cleaned_nodes.contains(left) && cleaned_nodes.contains(right) {
-                distance_vec[node_reference[left]][node_reference[right]].push(self.distances[i]);
-                distance_vec[node_reference[right]][node_reference[left]].push(self.distances[i]);
+            if let (Some(l), Some(r)) = (node_reference.get(left), node_reference.get(right)) {
+                distance_vec[*l][*r].push(self.distances[i]);
+                distance_vec[*r][*l].push(self.distances[i]);
             }
         }
 
-        let mut scalar_vec = vec![vec![0 as f64; cleaned_nodes.len()]; cleaned_nodes.len()];
+        let mut estimates = vec![vec![FilterEstimate::empty(); cleaned_nodes.len()]; cleaned_nodes.len()];
 
         // [TODO] Implement better cluster detection
         for i in 0..cleaned_nodes.len() {
@@ -163,71 +229,95 @@ impl DistanceGraph {
                     continue;
                 }
 
-                scalar_vec[i][j] = filter(&distance_vec[i][j]);
+                estimates[i][j] = filter.estimate(&distance_vec[i][j]);
             }
         }
 
-        let end = SystemTime::now();
-        let duration = end.duration_since(start);
-        println!("Finished Getting Distances for Beacons in {:?} milliseconds", duration);
-
-        let coordinates = self.get_coordinates(cleaned_nodes, scalar_vec);
-
-        return coordinates;
+        (cleaned_nodes, estimates)
     }
 
-    fn get_coordinates(&self, references: Vec<Identity>, distance_vec: Vec<Vec<f64>>) -> PolarCoordinates {
+    fn get_coordinates(&self, references: &[Identity], estimates: &[Vec<FilterEstimate>], geometry: &GeometryCheck, hint: Option<&HandednessHint>) -> Result<(MirrorResolution, Vec<Identity>)> {
         // [TODO] Support higher number of dimensions than 2.  This would require a data structure that handles any N number of dimensions.
+        if references.len() < 2 {
+            return Err(Error::InsufficientData { needed: 2, found: references.len() });
+        }
 
-        // Flatten and sort the distance vec to determine the size of the grid.  This doesn't need to be ordered or organized in any way, since it is just for defining the maximum size the grid needs to be in order to hold all positions.
-        let start = SystemTime::now();
-
+        let distance_vec: Vec<Vec<f64>> = estimates.iter().map(|row| row.iter().map(|e| e.value).collect()).collect();
         let mut calibration: Vec<(Identity, f64)> = vec![];
-        let mut skip_ids: Vec<usize> = vec![];
-        let mut calibration_ids: Vec<usize> = vec![];
 
         for i in 0..distance_vec.len() {
             calibration.push((references[i].clone(), distance_vec[0][i]));
-
-            if distance_vec[0][i] == 0.0 {
-                skip_ids.push(i);
-            }
         }
 
-        let end = SystemTime::now();
-        let duration = end.duration_since(start);
-        println!("Finished Calibration in {:?} milliseconds", duration);
+        let coincident: HashMap<Identity, Identity> = geometry
+            .nodes
+            .iter()
+            .filter_map(|(id, g)| match g {
+                NodeGeometry::Ambiguous(GeometryIssue::Coincident(partner)) => Some((id.clone(), partner.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let unlocalizable: Vec<Identity> = geometry.get_unlocalizable();
+
+        // The calibration radial needs a real length to turn the others from, so it is the first node with a range to the origin that isn't sitting on top of it.  Every other node has to be ranged to it as well, so one that can't be localized is only used when there is nothing else.
+        let is_calibration = |i: &usize| !estimates[0][*i].is_empty() && !coincident.contains_key(&references[*i]);
+        let calibration_idx = (1..references.len())
+            .find(|i| is_calibration(i) && !unlocalizable.contains(&references[*i]))
+            .or_else(|| (1..references.len()).find(is_calibration));
 
         // The origin is the first radial, and arbitrarily defines itself as a 0 degree "angled" radian, since the radian drawn is just from the first and second point.  This allows us to set an arbitrary calibration point to begin.
-        println!("Generating Origin Coordinates");
-        let mut origin_coordinates = PolarCoordinates::from_distances(references[0].clone(), references[1].clone(), vec![references[0].clone()], &calibration, &distance_vec);
+        let c = calibration_idx.ok_or_else(|| Error::DegenerateGeometry(format!("no node is ranged apart from the origin {}", references[0])))?;
 
-        // Once the origin coordinates are generated, we need to appropriately pick an offset.  We need this to be an angle other than the one chosen in the origin coordinates so we can arbitrarily set clockwise or counterclockwise as positive and negative for angles.  We then calibrate each other radial as positive or negative relative to the offset angle.
-        for r in &origin_coordinates.radials {
-            if r.1.angle == 0.0 {
-                skip_ids.push(references.iter().position(|x| x == &r.1.id).unwrap());
-            }
-        }
+        // Triangulating a node needs its range to both the origin and the calibration node.  Coincident nodes are copied from their partner afterwards instead, and nodes the geometry check found unlocalizable are left out rather than guessed at.
+        let skip_ids: Vec<Identity> = (0..references.len())
+            .filter(|i| *i == 0 || estimates[0][*i].is_empty() || (*i != c && (estimates[c][*i].is_empty() || unlocalizable.contains(&references[*i]))) || coincident.contains_key(&references[*i]))
+            .map(|i| references[i].clone())
+            .collect();
 
-        calibration_ids = references.iter().enumerate().filter(|x| !skip_ids.contains(&x.0)).map(|x| x.0).collect::<Vec<usize>>();
+        let origin_coordinates = PolarCoordinates::from_distances(references[0].clone(), references[c].clone(), skip_ids, &calibration, &distance_vec)?;
 
-        let skip_refs: Vec<Identity> = skip_ids.iter().map(|x| references[*x].clone()).collect();
+        // The law of cosines only gives how far each radial is turned from the calibration radial, not which way, so every node is then put on whichever side agrees best with the rest of its distances.
+        let mut resolution = mirror::resolve_mirror(&origin_coordinates, references, &distance_vec, hint, &MirrorConfig::default());
 
-        let mut offset_coordinates = PolarCoordinates::from_distances(references[0].clone(), references[calibration_ids[0]].clone(), skip_refs.clone(), &calibration, &distance_vec);
+        for id in references {
+            if let Some(partner) = coincident.get(id) {
+                let position = if *partner == resolution.coordinates.origin {
+                    Some(Radial::empty(id.clone()))
+                } else {
+                    resolution.coordinates.get(partner).map(|r| Radial { id: id.clone(), ..r.clone() })
+                };
 
-        // We do a second pass basing the distance from the "2" identity, to confirm if the other thetas are positive or negative.  If the distance to the "2" identity is correct, they remain positive, but if it should be further, they will be negative.
-        for i in 0..references.len() {
-            if skip_ids.contains(&i) {
-                continue;
+                if let Some(position) = position {
+                    resolution.coordinates.add_radial(position);
+                }
             }
+        }
 
-            let offset_angle = origin_coordinates.get(&references[calibration_ids[0]]).unwrap().angle;
+        // A node the geometry check found ambiguous was placed on whichever side fit best, but the other side fits too, so it is flagged whether or not the mirror check could tell.  The origin and calibration node define the frame, so they can't be on the wrong side of it.
+        for id in geometry.get_ambiguous() {
+            let is_frame = id == references[0] || id == references[c];
 
-            origin_coordinates.reconcile_radial(offset_angle, references[i].clone(), offset_coordinates.get(&references[i].clone()).unwrap());
+            if !is_frame && !coincident.contains_key(&id) && resolution.coordinates.get(&id).is_some() && !resolution.ambiguous.contains(&id) {
+                resolution.ambiguous.push(id);
+            }
         }
 
-        println!("Completed Coordinate Processing");
+        let unplaced: Vec<Identity> = references[1..]
+            .iter()
+            .filter(|id| resolution.coordinates.get(id).is_none())
+            .cloned()
+            .collect();
 
-        return origin_coordinates;
+        Ok((resolution, unplaced))
     }
-}
\ No newline at end of file
+}
+
+// A triangulated position graph along with everything the solve had to work around.
+#[derive(Clone, Debug)]
+pub struct PositionSolution {
+    pub resolution: MirrorResolution,
+    pub geometry: GeometryCheck,
+    // Nodes left out of the coordinates, either found unlocalizable by the geometry check or missing a range to the origin or calibration node.  Ambiguous nodes are placed, and listed in resolution.ambiguous.
+    pub unplaced: Vec<Identity>,
+}
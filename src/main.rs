@@ -1,32 +1,39 @@
 use std::{sync::Arc, thread::sleep, time::Duration};
 
-mod agent;
-mod agent_manager;
-mod beacon;
-mod filter;
-mod identity;
-mod location;
-mod polar;
-mod signal;
 mod test_suite;
 
-use filter::beam_deviation_filter;
+use navigation::filter::BeamDeviationFilter;
 use test_suite::*;
 
-use crate::{location::{MomentEdge, DistanceGraph}, identity::Identity, polar::{PolarCoordinates, Radial, add_radials}, agent::Agent, beacon::beacon::Beacon};
-use crate::polar::get_unknown_triangle_angle;
+use navigation::{navigation::Navigator, identity::Identity, point::Point2, polar::Radial, agent::Agent, beacon::{Beacon, BeaconSignal, UdpTransport}};
+use navigation::clock::SimulatedClock;
+use navigation::simulation::{NoiseModel, RangingConfig, RangingSimulator};
 
 use std::time::SystemTime;
 
-use datetime::LocalDateTime;
 use rand::prelude::*;
 use rand::thread_rng;
 
 // [TODO] Create testing to prove grid created was resolved correctly.
 
+// The library does the work, this just runs one of the demo scenarios against it.  Pick one with the first argument, e.g. `cargo run -- navigator`.
 fn main() {
+    let scenario = std::env::args().nth(1).unwrap_or_else(|| "beacons".into());
+
     page_break();
-    test_polar_coordinate_beacons();
+
+    match scenario.as_str() {
+        "beacons" => test_polar_coordinate_beacons(),
+        "tracking" => test_multi_beacon_tracking(),
+        "movement" => test_movement_one(),
+        "movement-many" => test_movement_many(),
+        "coordinates" => test_coordinates(),
+        "position-graph" => test_position_graph(),
+        "navigator" => test_navigator(),
+        "udp-beacon" => test_udp_beacon(std::env::args().nth(2).unwrap_or_else(|| "0".into()).into()),
+        other => println!("Unknown scenario {:?}, expected one of beacons, tracking, movement, movement-many, coordinates, position-graph, navigator or udp-beacon.", other),
+    }
+
     page_break();
 }
 
@@ -40,13 +47,9 @@ fn test_movement_many() {
 fn test_polar_coordinate_beacons() {
     println!("Initializing Coordinates");
     println!("Setting Configs");
-    let max_distance = 100.0;
     println!("Creating Beacons");
 
     // [TODO] Create beacons with polar coordinates.
-    // let beacon_positions = create_beacon_polar(10, max_distance);
-
-    // println!("beacon_positions: {:?}", beacon_positions);
 
     println!("testing beacon thread");
     
@@ -56,8 +59,11 @@ fn test_polar_coordinate_beacons() {
         let mut beacon_2 = Beacon::new("1".into(), Radial {id: "1".into(), radius: 0.0, angle: 0.0});
         beacon_2.listen();
         sleep(Duration::from_millis(1000));
-        beacon.stop();
-        beacon_2.stop();
+        for result in [beacon.stop(), beacon_2.stop()] {
+            if let Err(e) = result {
+                println!("beacon didn't stop cleanly: {}", e);
+            }
+        }
     }
 
     println!("stop called");
@@ -65,12 +71,46 @@ fn test_polar_coordinate_beacons() {
 
 }
 
+// Run several of these at once, each in its own terminal with its own id, e.g. `cargo run -- udp-beacon 1`.  They find each other over multicast on the loopback interface.
+fn test_udp_beacon(id: Identity) {
+    let transport = match UdpTransport::multicast([239, 255, 0, 1].into(), 47_000) {
+        Ok(transport) => transport,
+        Err(e) => {
+            println!("couldn't join the multicast group: {}", e);
+            return;
+        }
+    };
+
+    let mut beacon = Beacon::new(id.clone(), Radial::empty(id.clone()));
+    beacon.listen_on(Arc::new(transport));
+
+    for _ in 0..25 {
+        let signal = BeaconSignal { id: id.clone(), distance: thread_rng().gen_range(1.0..10.0), timestamp: SystemTime::now() };
+
+        if let Err(e) = beacon.broadcast(&signal) {
+            println!("couldn't broadcast: {}", e);
+        }
+
+        sleep(Duration::from_millis(200));
+
+        for edge in beacon.get_distances() {
+            println!("{} hears {} at {:.2}", id, edge.right, edge.distance);
+        }
+    }
+
+    println!("{} dropped {} signals it couldn't read", id, beacon.get_dropped());
+
+    if let Err(e) = beacon.stop() {
+        println!("beacon didn't stop cleanly: {}", e);
+    }
+}
+
 fn test_multi_beacon_tracking() {
     println!("Initializing Coordinates");
     println!("Setting Configs");
     let grid = (10, 10);
     println!("Creating Beacons");
-    let beacon_positions = create_beacon_grid(10, grid);
+    let _beacon_positions = create_beacon_grid(10, grid);
     // [TODO] Properly manage multiple beacons, with them tracking movement of each agent separately.
     // [TODO] Have beacons actually construct agent position based on distance from agent.  Origin becomes much messier without the universal reference frame.
     // [TODO] Have differing updates for beacons, where the agent moves in real time, and the beacons have to piece together where the agent is.
@@ -85,22 +125,24 @@ fn test_movement_one() {
     let grid = (10, 10);
     println!("Creating Beacons");
     let beacon_nodes = create_nodes_with_positions(10, grid);
-    let beacons: Vec<Identity> = beacon_nodes.iter().cloned().map(|x| {x.0.clone()}).collect();
+    let beacons: Vec<Identity> = beacon_nodes.iter().map(|x| {x.0.clone()}).collect();
     let beacon_graph = get_distance_graph(beacon_nodes);
-    let beacon_coords = beacon_graph.get_position_graph(&beacons, &beam_deviation_filter);
+    let _beacon_coords = beacon_graph.get_position_graph(&beacons, &BeamDeviationFilter::default());
     println!("Beacons Created");
 
     println!("Creating Agents");
     let agent_nodes = create_nodes_with_positions(10, grid);
-    let agents: Vec<Identity> = agent_nodes.iter().cloned().map(|x| {x.0.clone()}).collect();
-    let mut agent_graph = get_distance_graph(agent_nodes.clone());
-    let mut agent_coords = agent_graph.get_position_graph(&agents, &beam_deviation_filter);
+    let agents: Vec<Identity> = agent_nodes.iter().map(|x| {x.0.clone()}).collect();
+    let agent_graph = get_distance_graph(agent_nodes.clone());
+    let agent_coords = agent_graph.get_position_graph(&agents, &BeamDeviationFilter::default()).expect("agent graph should solve");
     println!("Agents Created");
 
     line_break();
     let mut rng = thread_rng();
     
-    let mut agent = Agent::new("0".into(), 0, agent_coords.radials.get(&agent_nodes[1].0).unwrap().clone(), 100.0);
+    // Simulated time, so the agent moves as fast as the loop below advances the clock instead of waiting on the wall clock.
+    let clock = SimulatedClock::new(SystemTime::now());
+    let mut agent = Agent::with_clock("0".into(), 0, agent_coords.radials.get(&agent_nodes[1].0).unwrap().clone(), 100.0, clock.shared());
     
     println!("Agent: {:?}", agent);
     let mut radials: Vec<Radial> = vec![];
@@ -120,6 +162,7 @@ fn test_movement_one() {
     
     println!("Moving Agent {} nodes", radials.len());
     while agent.current_coord.is_some() {
+        clock.advance(Duration::from_millis(1));
         agent.get_position();
     }
     println!("Agent Moved");
@@ -134,15 +177,14 @@ fn test_movement_one() {
 
 fn test_coordinates() {
     let nodes = create_nodes_with_positions(10, (10, 10));
-    let beacons: Vec<Identity> = nodes.iter().cloned().map(|x| {x.0.clone()}).collect();
+    let beacons: Vec<Identity> = nodes.iter().map(|x| {x.0.clone()}).collect();
     println!("beacons: {:?}", beacons);
     line_break();
-    let grid = create_grid(&nodes);
-    display_grid(grid);
+    display_nodes(nodes.clone());
     line_break();
     let dg = get_distance_graph(nodes);
     println!("distance graph created.");
-    println!("coords: {:?}", dg.get_position_graph(&beacons, &beam_deviation_filter));
+    println!("coords: {:?}", dg.get_position_graph(&beacons, &BeamDeviationFilter::default()));
 }
 
 fn test_position_graph() {
@@ -156,29 +198,32 @@ fn test_position_graph() {
     "h".into(),
     ];
 
-    let mut edges: Vec<MomentEdge> = vec![];
+    // Scattered over a 10 x 10 room, ranged by UWB with some multipath thrown in.
     let mut rng = thread_rng();
+    let positions: Vec<(Identity, Point2)> = beacons.iter().map(|id| (id.clone(), Point2::new(rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0)))).collect();
+    let config = RangingConfig { noise: NoiseModel::Laplace(0.1), dropout: 0.05, ..RangingConfig::default() };
 
-    for i in 0..beacons.len() {
-        for j in (i + 1)..beacons.len() {
-            if i == j {
-                continue;
-            }
-
-            for _ in 0..1_000 {
-                let mult: f64 = rng.gen();
-                let distance = ((j - i) as f64) * (mult + 0.5);
-                // println!("rand: {:?}, dist: {:?}", mult, distance);
-                edges.push(MomentEdge::new(beacons[i].clone(), beacons[j].clone(), distance, SystemTime::now()));
-            }
-        }
-    }
+    let dg = RangingSimulator::new(config).get_distance_graph(&positions, 1_000, SystemTime::now());
 
-    let mut dg = DistanceGraph::from_edges(edges);
+    // The library stays quiet, so the timing of each stage is measured out here.
+    let start = SystemTime::now();
+    let _ = dg.get_filtered_distances(&beacons, &BeamDeviationFilter::default());
+    println!("Finished Getting Distances for Beacons in {:?}", start.elapsed().unwrap_or_default());
 
     let start = SystemTime::now();
-    dg.get_position_graph(&beacons, &beam_deviation_filter);
-    let end = SystemTime::now();
-    let duration = end.duration_since(start);
-    println!("in {:?} milliseconds", duration);
-}
\ No newline at end of file
+    let _ = dg.get_position_graph(&beacons, &BeamDeviationFilter::default());
+    println!("Solved the position graph in {:?}", start.elapsed().unwrap_or_default());
+}
+
+fn test_navigator() {
+    let nodes = create_nodes_with_positions(10, (10, 10));
+    let mut navigator = Navigator::new("navigator".into());
+
+    navigator.extend(get_distance_graph(nodes.clone()).edges());
+    println!("nodes: {:?}", navigator.get_nodes());
+
+    for node in &nodes {
+        let resolved = navigator.get_node_location(&node.0, &BeamDeviationFilter::default());
+        println!("{} at ({}, {}): {:?}", node.0, node.1, node.2, resolved.map(|r| r.position.to_degrees()));
+    }
+}
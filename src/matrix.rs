@@ -0,0 +1,260 @@
+// Small dense matrix for the estimators.  The matrices involved are tiny (state
+// vectors and per-swarm distance matrices), so this favors being simple over
+// being fast.
+
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    data: Vec<f64>,
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+    fn index(&self, (row, col): (usize, usize)) -> &f64 {
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut f64 {
+        &mut self.data[row * self.cols + col]
+    }
+}
+
+impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Matrix {
+        Matrix {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+
+    pub fn identity(size: usize) -> Matrix {
+        let mut output = Matrix::zeros(size, size);
+
+        for i in 0..size {
+            output[(i, i)] = 1.0;
+        }
+
+        output
+    }
+
+    pub fn from_rows(rows: &[Vec<f64>]) -> Matrix {
+        let cols = rows.first().map_or(0, |r| r.len());
+        let mut output = Matrix::zeros(rows.len(), cols);
+
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(row.len(), cols, "Every row of a matrix needs the same number of columns.");
+
+            for (j, value) in row.iter().enumerate() {
+                output[(i, j)] = *value;
+            }
+        }
+
+        output
+    }
+
+    pub fn column(values: &[f64]) -> Matrix {
+        Matrix {
+            rows: values.len(),
+            cols: 1,
+            data: values.to_vec(),
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut output = Matrix::zeros(self.cols, self.rows);
+
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                output[(j, i)] = self[(i, j)];
+            }
+        }
+
+        output
+    }
+
+    pub fn scale(&self, scalar: f64) -> Matrix {
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().map(|x| x * scalar).collect(),
+        }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting.  Returns None when the matrix is singular (or close enough to it that the inverse would be garbage).
+    pub fn inverse(&self) -> Option<Matrix> {
+        assert_eq!(self.rows, self.cols, "Only square matrices can be inverted.");
+
+        let size = self.rows;
+        let mut working = self.clone();
+        let mut output = Matrix::identity(size);
+
+        for col in 0..size {
+            let pivot = (col..size).max_by(|a, b| working[(*a, col)].abs().total_cmp(&working[(*b, col)].abs()))?;
+
+            if working[(pivot, col)].abs() < 1e-12 {
+                return None;
+            }
+
+            working.swap_rows(col, pivot);
+            output.swap_rows(col, pivot);
+
+            let divisor = working[(col, col)];
+
+            for j in 0..size {
+                working[(col, j)] /= divisor;
+                output[(col, j)] /= divisor;
+            }
+
+            for row in 0..size {
+                if row == col {
+                    continue;
+                }
+
+                let factor = working[(row, col)];
+
+                if factor == 0.0 {
+                    continue;
+                }
+
+                for j in 0..size {
+                    working[(row, j)] -= factor * working[(col, j)];
+                    output[(row, j)] -= factor * output[(col, j)];
+                }
+            }
+        }
+
+        Some(output)
+    }
+
+    // Eigen decomposition of a symmetric matrix by cyclic Jacobi rotations.  Returns the eigenvalues in descending order with the matching eigenvectors as the columns of the second matrix.
+    pub fn symmetric_eigen(&self) -> (Vec<f64>, Matrix) {
+        assert_eq!(self.rows, self.cols, "Only square matrices have an eigen decomposition.");
+
+        let size = self.rows;
+        let mut working = self.clone();
+        let mut vectors = Matrix::identity(size);
+
+        for _ in 0..100 {
+            let off_diagonal: f64 = (0..size).flat_map(|i| (0..size).filter(move |j| *j != i).map(move |j| (i, j))).map(|(i, j)| working[(i, j)].powi(2)).sum();
+
+            if off_diagonal < 1e-22 {
+                break;
+            }
+
+            for p in 0..size {
+                for q in (p + 1)..size {
+                    if working[(p, q)].abs() < 1e-300 {
+                        continue;
+                    }
+
+                    let theta = (working[(q, q)] - working[(p, p)]) / (2.0 * working[(p, q)]);
+                    let t = theta.signum() / (theta.abs() + (theta.powi(2) + 1.0).sqrt());
+                    let c = 1.0 / (t.powi(2) + 1.0).sqrt();
+                    let s = t * c;
+
+                    for k in 0..size {
+                        let kp = working[(k, p)];
+                        let kq = working[(k, q)];
+                        working[(k, p)] = c * kp - s * kq;
+                        working[(k, q)] = s * kp + c * kq;
+                    }
+
+                    for k in 0..size {
+                        let pk = working[(p, k)];
+                        let qk = working[(q, k)];
+                        working[(p, k)] = c * pk - s * qk;
+                        working[(q, k)] = s * pk + c * qk;
+                    }
+
+                    for k in 0..size {
+                        let kp = vectors[(k, p)];
+                        let kq = vectors[(k, q)];
+                        vectors[(k, p)] = c * kp - s * kq;
+                        vectors[(k, q)] = s * kp + c * kq;
+                    }
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..size).collect();
+        order.sort_by(|a, b| working[(*b, *b)].total_cmp(&working[(*a, *a)]));
+
+        let values = order.iter().map(|i| working[(*i, *i)]).collect();
+        let mut sorted_vectors = Matrix::zeros(size, size);
+
+        for (col, i) in order.iter().enumerate() {
+            for row in 0..size {
+                sorted_vectors[(row, col)] = vectors[(row, *i)];
+            }
+        }
+
+        (values, sorted_vectors)
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+
+        for j in 0..self.cols {
+            self.data.swap(a * self.cols + j, b * self.cols + j);
+        }
+    }
+}
+
+impl Add for &Matrix {
+    type Output = Matrix;
+    fn add(self, other: &Matrix) -> Matrix {
+        assert_eq!((self.rows, self.cols), (other.rows, other.cols), "Matrix dimensions must match to add.");
+
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().zip(&other.data).map(|(a, b)| a + b).collect(),
+        }
+    }
+}
+
+impl Sub for &Matrix {
+    type Output = Matrix;
+    fn sub(self, other: &Matrix) -> Matrix {
+        assert_eq!((self.rows, self.cols), (other.rows, other.cols), "Matrix dimensions must match to subtract.");
+
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().zip(&other.data).map(|(a, b)| a - b).collect(),
+        }
+    }
+}
+
+impl Mul for &Matrix {
+    type Output = Matrix;
+    fn mul(self, other: &Matrix) -> Matrix {
+        assert_eq!(self.cols, other.rows, "Matrix dimensions must line up to multiply.");
+
+        let mut output = Matrix::zeros(self.rows, other.cols);
+
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let value = self[(i, k)];
+
+                if value == 0.0 {
+                    continue;
+                }
+
+                for j in 0..other.cols {
+                    output[(i, j)] += value * other[(k, j)];
+                }
+            }
+        }
+
+        output
+    }
+}
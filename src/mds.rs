@@ -0,0 +1,410 @@
+// Multidimensional scaling for whole swarm relative localization.  Instead of
+// triangulating everything off of two anchors, every filtered pairwise distance
+// is fit at once.  Classical MDS gives a closed form starting layout, and SMACOF
+// (stress majorization) refines it while weighting each pair by how much the
+// filter trusted it.  Pairs with no measurements get a weight of 0.0 and are
+// left out of the fit entirely.
+
+use crate::error::{Error, Result};
+use crate::identity::Identity;
+use crate::matrix::Matrix;
+use crate::point::Point2;
+use crate::polar::{PolarCoordinates, SphericalCoordinates, SphericalRadial};
+
+use std::f64::consts::PI;
+
+#[derive(Clone, Debug)]
+pub struct MdsConfig {
+    pub max_iterations: usize,
+    // Stop once an iteration improves the stress by less than this fraction.
+    pub tolerance: f64,
+}
+
+impl Default for MdsConfig {
+    fn default() -> Self {
+        MdsConfig {
+            max_iterations: 300,
+            tolerance: 1e-9,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MdsSolution {
+    // One row per node, with one column per dimension.
+    pub points: Vec<Vec<f64>>,
+    // Weighted sum of squared differences between the measured distances and the solved layout.
+    pub stress: f64,
+    pub iterations: usize,
+}
+
+// Fills in missing pairs with the shortest path through measured pairs.  This overestimates the real distance, but classical MDS needs a complete matrix and it only has to be good enough to seed SMACOF.  Pairs in disconnected parts of the graph get the largest known distance.
+fn get_completed_distances(distances: &[Vec<f64>], weights: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let size = distances.len();
+    let mut completed = vec![vec![f64::INFINITY; size]; size];
+
+    for i in 0..size {
+        completed[i][i] = 0.0;
+
+        for j in 0..size {
+            if i != j && weights[i][j] > 0.0 {
+                completed[i][j] = distances[i][j];
+            }
+        }
+    }
+
+    for k in 0..size {
+        for i in 0..size {
+            for j in 0..size {
+                let through = completed[i][k] + completed[k][j];
+
+                if through < completed[i][j] {
+                    completed[i][j] = through;
+                }
+            }
+        }
+    }
+
+    let largest = completed.iter().flatten().cloned().filter(|d| d.is_finite()).fold(0.0, f64::max);
+
+    for row in completed.iter_mut() {
+        for value in row.iter_mut() {
+            if !value.is_finite() {
+                *value = largest;
+            }
+        }
+    }
+
+    completed
+}
+
+// Double centers the squared distances and keeps the largest eigenvectors, one per dimension, as the layout.
+pub fn classical_mds(distances: &[Vec<f64>], dimensions: usize) -> Vec<Vec<f64>> {
+    let size = distances.len();
+
+    if size == 0 {
+        return vec![];
+    }
+
+    let mut squared = Matrix::zeros(size, size);
+
+    for i in 0..size {
+        for j in 0..size {
+            squared[(i, j)] = distances[i][j].powi(2);
+        }
+    }
+
+    let mut centering = Matrix::identity(size);
+
+    for i in 0..size {
+        for j in 0..size {
+            centering[(i, j)] -= 1.0 / size as f64;
+        }
+    }
+
+    let gram = (&(&centering * &squared) * &centering).scale(-0.5);
+    let (values, vectors) = gram.symmetric_eigen();
+
+    let scales: Vec<f64> = (0..dimensions).map(|k| values.get(k).map_or(0.0, |v| v.max(0.0).sqrt())).collect();
+
+    // A swarm smaller than the number of dimensions has fewer eigenvectors than axes, and the extra axes are left at 0.0.
+    (0..size)
+        .map(|i| (0..dimensions).map(|k| if k < size { vectors[(i, k)] * scales[k] } else { 0.0 }).collect())
+        .collect()
+}
+
+fn get_stress(distances: &[Vec<f64>], weights: &[Vec<f64>], points: &[Vec<f64>]) -> f64 {
+    let mut stress = 0.0;
+
+    for i in 0..points.len() {
+        for j in (i + 1)..points.len() {
+            if weights[i][j] > 0.0 {
+                stress += weights[i][j] * (distances[i][j] - get_range(&points[i], &points[j])).powi(2);
+            }
+        }
+    }
+
+    stress
+}
+
+fn get_range(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+}
+
+// Weighted SMACOF using the Guttman transform.  Each iteration is guaranteed not to increase the stress.
+pub fn smacof(distances: &[Vec<f64>], weights: &[Vec<f64>], initial: Vec<Vec<f64>>, config: &MdsConfig) -> MdsSolution {
+    let size = initial.len();
+    let mut points = initial;
+    let mut stress = get_stress(distances, weights, &points);
+
+    if size < 2 {
+        return MdsSolution { points, stress, iterations: 0 };
+    }
+
+    let mut v = Matrix::zeros(size, size);
+
+    for i in 0..size {
+        for j in 0..size {
+            if i != j {
+                v[(i, j)] = -weights[i][j];
+                v[(i, i)] += weights[i][j];
+            }
+        }
+    }
+
+    // V is singular (its rows sum to zero), so its pseudo-inverse is built from (V + 11^T)^-1 - 11^T / n^2.  This only fails when the weights split the swarm into disconnected pieces, in which case the seed is as good as it gets.
+    let mut shifted = v.clone();
+
+    for i in 0..size {
+        for j in 0..size {
+            shifted[(i, j)] += 1.0;
+        }
+    }
+
+    let mut v_pseudo = match shifted.inverse() {
+        Some(inverse) => inverse,
+        None => return MdsSolution { points, stress, iterations: 0 },
+    };
+
+    for i in 0..size {
+        for j in 0..size {
+            v_pseudo[(i, j)] -= 1.0 / (size as f64).powi(2);
+        }
+    }
+
+    let mut iterations = 0;
+
+    while iterations < config.max_iterations {
+        iterations += 1;
+
+        let mut b = Matrix::zeros(size, size);
+
+        for i in 0..size {
+            for j in 0..size {
+                if i == j || weights[i][j] <= 0.0 {
+                    continue;
+                }
+
+                let range = get_range(&points[i], &points[j]);
+
+                if range > 1e-12 {
+                    b[(i, j)] = -weights[i][j] * distances[i][j] / range;
+                    b[(i, i)] += weights[i][j] * distances[i][j] / range;
+                }
+            }
+        }
+
+        let current = Matrix::from_rows(&points);
+        let next = &(&v_pseudo * &b) * &current;
+
+        points = (0..size).map(|i| (0..next.cols).map(|k| next[(i, k)]).collect()).collect();
+
+        let next_stress = get_stress(distances, weights, &points);
+        let improvement = stress - next_stress;
+        stress = next_stress;
+
+        if improvement <= config.tolerance * stress.max(1e-12) {
+            break;
+        }
+    }
+
+    MdsSolution { points, stress, iterations }
+}
+
+// `weights[i][j]` of 0.0 marks a missing pair.  Both matrices are expected to be symmetric.
+pub fn solve(distances: &[Vec<f64>], weights: &[Vec<f64>], dimensions: usize, config: &MdsConfig) -> MdsSolution {
+    let seed = classical_mds(&get_completed_distances(distances, weights), dimensions);
+    smacof(distances, weights, seed, config)
+}
+
+// Every reference needs a point, and every point needs at least dimensions coordinates.
+fn check_points(references: &[Identity], points: &[Vec<f64>], dimensions: usize) -> Result<()> {
+    if references.is_empty() || points.len() < references.len() {
+        return Err(Error::InsufficientData { needed: references.len().max(1), found: points.len().min(references.len()) });
+    }
+
+    match points[..references.len()].iter().find(|p| p.len() < dimensions) {
+        Some(short) => Err(Error::InsufficientData { needed: dimensions, found: short.len() }),
+        None => Ok(()),
+    }
+}
+
+// Puts the layout into the same frame get_coordinates uses: the first reference is the origin, and the first other node away from it sits on the 0 angle.  Which way is counterclockwise is arbitrary, since distances alone can't tell a layout from its mirror image.  Needs a point with at least two dimensions for every reference.
+pub fn to_polar_coordinates(references: &[Identity], points: &[Vec<f64>]) -> Result<PolarCoordinates> {
+    check_points(references, points, 2)?;
+
+    let mut output = PolarCoordinates::new(references[0].clone());
+    let origin = Point2::new(points[0][0], points[0][1]);
+
+    let offset_angle = points
+        .iter()
+        .skip(1)
+        .map(|p| Point2::new(p[0], p[1]) - origin)
+        .find(|p| p.length() > 1e-9)
+        .map_or(0.0, |p| p.angle());
+
+    for i in 1..references.len() {
+        let offset = Point2::new(points[i][0], points[i][1]) - origin;
+        let mut radial = offset.to_radial(references[i].clone());
+
+        if radial.radius > 1e-9 {
+            radial.angle = (radial.angle - offset_angle).rem_euclid(PI * 2.0);
+        }
+
+        output.add_radial(radial);
+    }
+
+    Ok(output)
+}
+
+fn get_difference(a: &[f64], b: &[f64]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn get_dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn get_normalized(v: [f64; 3]) -> Option<[f64; 3]> {
+    let length = get_dot(&v, &v).sqrt();
+
+    if length < 1e-9 {
+        return None;
+    }
+
+    Some([v[0] / length, v[1] / length, v[2] / length])
+}
+
+// The 3D version of to_polar_coordinates.  The first reference is the origin, the first node away from it sets the 0 azimuth, and the first node off of that line sets the 0 elevation plane.  Up and down are as arbitrary as clockwise is in 2D.  Needs a point with at least three dimensions for every reference.
+pub fn to_spherical_coordinates(references: &[Identity], points: &[Vec<f64>]) -> Result<SphericalCoordinates> {
+    check_points(references, points, 3)?;
+
+    let mut output = SphericalCoordinates::new(references[0].clone());
+    let origin = &points[0];
+
+    // Gram-Schmidt the first two usable directions into a frame.
+    let x_axis = points.iter().skip(1).find_map(|p| get_normalized(get_difference(p, origin))).unwrap_or([1.0, 0.0, 0.0]);
+
+    let y_axis = points
+        .iter()
+        .skip(1)
+        .find_map(|p| {
+            let v = get_difference(p, origin);
+            let along = get_dot(&v, &x_axis);
+            get_normalized([v[0] - along * x_axis[0], v[1] - along * x_axis[1], v[2] - along * x_axis[2]])
+        })
+        .unwrap_or_else(|| {
+            // Every node is on one line, so any perpendicular direction will do.
+            let helper = if x_axis[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+            let along = get_dot(&helper, &x_axis);
+            get_normalized([helper[0] - along * x_axis[0], helper[1] - along * x_axis[1], helper[2] - along * x_axis[2]]).unwrap_or([0.0, 1.0, 0.0])
+        });
+
+    let z_axis = [
+        x_axis[1] * y_axis[2] - x_axis[2] * y_axis[1],
+        x_axis[2] * y_axis[0] - x_axis[0] * y_axis[2],
+        x_axis[0] * y_axis[1] - x_axis[1] * y_axis[0],
+    ];
+
+    for i in 1..references.len() {
+        let v = get_difference(&points[i], origin);
+
+        output.add_radial(SphericalRadial::from_cartesian(references[i].clone(), get_dot(&v, &x_axis), get_dot(&v, &y_axis), get_dot(&v, &z_axis)));
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MedianFilter;
+    use crate::location::DistanceGraph;
+    use crate::polar::add_spherical_radials;
+
+    fn get_layout() -> Vec<Vec<f64>> {
+        vec![vec![0.0, 0.0], vec![4.0, 0.0], vec![4.0, 3.0], vec![0.0, 5.0], vec![-2.0, 1.0], vec![2.0, -3.0]]
+    }
+
+    fn get_distances(points: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        points.iter().map(|a| points.iter().map(|b| get_range(a, b)).collect()).collect()
+    }
+
+    // Every pairwise distance in the solution should match the layout, which holds regardless of rotation or reflection.
+    fn assert_same_shape(solution: &[Vec<f64>], expected: &[Vec<f64>]) {
+        for i in 0..expected.len() {
+            for j in 0..expected.len() {
+                let error = (get_range(&solution[i], &solution[j]) - get_range(&expected[i], &expected[j])).abs();
+                assert!(error < 1e-3, "{} {} off by {}", i, j, error);
+            }
+        }
+    }
+
+    #[test]
+    fn recovers_complete_layout() {
+        let layout = get_layout();
+        let weights = vec![vec![1.0; layout.len()]; layout.len()];
+        let solution = solve(&get_distances(&layout), &weights, 2, &MdsConfig::default());
+
+        assert_same_shape(&solution.points, &layout);
+        assert!(solution.stress < 1e-6);
+    }
+
+    #[test]
+    fn polar_frame_needs_two_dimensions() {
+        let layout = get_layout();
+        let references: Vec<Identity> = (0..layout.len()).map(|i| i.to_string().into()).collect();
+
+        let coordinates = to_polar_coordinates(&references, &layout).unwrap();
+        assert!((coordinates[&"1".into()].radius - 4.0).abs() < 1e-9 && coordinates[&"1".into()].angle.abs() < 1e-9);
+
+        let line: Vec<Vec<f64>> = layout.iter().map(|p| vec![p[0]]).collect();
+        assert_eq!(to_polar_coordinates(&references, &line).err(), Some(Error::InsufficientData { needed: 2, found: 1 }));
+        assert_eq!(to_polar_coordinates(&[], &[]).err(), Some(Error::InsufficientData { needed: 1, found: 0 }));
+
+        let empty = DistanceGraph::new().get_mds_position_graph(&[], &MedianFilter, &MdsConfig::default());
+        assert_eq!(empty.err(), Some(Error::InsufficientData { needed: 1, found: 0 }));
+    }
+
+    #[test]
+    fn recovers_layout_with_missing_pairs() {
+        let layout = get_layout();
+        let mut weights = vec![vec![1.0; layout.len()]; layout.len()];
+
+        for (i, j) in [(0, 2), (1, 3), (4, 5)] {
+            weights[i][j] = 0.0;
+            weights[j][i] = 0.0;
+        }
+
+        let solution = solve(&get_distances(&layout), &weights, 2, &MdsConfig::default());
+        assert_same_shape(&solution.points, &layout);
+    }
+
+    #[test]
+    fn recovers_layout_in_three_dimensions() {
+        let layout = vec![vec![0.0, 0.0, 0.0], vec![4.0, 0.0, 1.0], vec![4.0, 3.0, -2.0], vec![0.0, 5.0, 3.0], vec![-2.0, 1.0, 0.5], vec![2.0, -3.0, 4.0]];
+        let weights = vec![vec![1.0; layout.len()]; layout.len()];
+        let solution = solve(&get_distances(&layout), &weights, 3, &MdsConfig::default());
+
+        assert_same_shape(&solution.points, &layout);
+
+        let references: Vec<Identity> = (0..layout.len()).map(|i| i.to_string().into()).collect();
+        let coordinates = to_spherical_coordinates(&references, &solution.points).unwrap();
+        let error = (coordinates.get(&"1".into()).unwrap().get_distance(coordinates.get(&"5".into()).unwrap()) - get_range(&layout[1], &layout[5])).abs();
+        assert!(error < 1e-3);
+    }
+
+    #[test]
+    fn spherical_frame_needs_three_dimensions() {
+        let layout = get_layout();
+        let references: Vec<Identity> = (0..layout.len()).map(|i| i.to_string().into()).collect();
+
+        assert_eq!(to_spherical_coordinates(&references, &layout).err(), Some(Error::InsufficientData { needed: 3, found: 2 }));
+        assert_eq!(to_spherical_coordinates(&[], &[]).err(), Some(Error::InsufficientData { needed: 1, found: 0 }));
+        assert_eq!(to_spherical_coordinates(&references, &layout[..2]).err(), Some(Error::InsufficientData { needed: 6, found: 2 }));
+
+        let empty = DistanceGraph::new().get_spherical_position_graph(&[], &MedianFilter, &MdsConfig::default());
+        assert_eq!(empty.err(), Some(Error::InsufficientData { needed: 1, found: 0 }));
+        assert_eq!(add_spherical_radials(&[]), None);
+    }
+}
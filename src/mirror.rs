@@ -0,0 +1,243 @@
+// Resolves the mirror ambiguity left over from triangulating off of two nodes.
+// The law of cosines only gives the size of the angle between the origin ->
+// calibration line and a node, never which side of the line the node is on.  Each
+// node is placed on whichever side agrees best with all of its distances to the
+// nodes placed before it, instead of trusting a single offset node.
+//
+// The handedness of the whole frame can't be recovered from distances at all, since
+// a mirrored swarm has exactly the same distances.  Without a hint the first
+// off-line node is arbitrarily put counterclockwise, and a hint can flip that.
+
+use crate::identity::Identity;
+use crate::point::Point2;
+use crate::polar::{Angle, PolarCoordinates, Radial};
+
+use std::f64::consts::PI;
+
+#[derive(Clone, Debug)]
+pub enum HandednessHint {
+    // Compass bearings (clockwise from north) from the origin to two nodes.  Only which way the second is turned from the first is used, so the compass doesn't need to be calibrated against the frame.
+    Compass { first: (Identity, Angle), second: (Identity, Angle) },
+    // A node whose position is already known in a frame with the same origin and with the calibration node on the 0 angle, e.g. a surveyed third anchor.  Only the side of the calibration line it sits on is used.
+    Anchor(Radial),
+}
+
+#[derive(Clone, Debug)]
+pub struct MirrorConfig {
+    // A node is ambiguous when the RMS distance error of its two possible sides differ by less than this.
+    pub tolerance: f64,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        MirrorConfig {
+            tolerance: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MirrorResolution {
+    pub coordinates: PolarCoordinates,
+    // Nodes whose side couldn't be told apart from their distances, either because both sides fit equally well or because no distances to already placed nodes were measured.
+    pub ambiguous: Vec<Identity>,
+    // True when a hint was given and could be used to pin the handedness of the frame.
+    pub hinted: bool,
+    // True when the hint flipped the frame away from the arbitrary default.
+    pub mirrored: bool,
+}
+
+impl MirrorResolution {
+    pub fn is_ambiguous(&self) -> bool {
+        !self.ambiguous.is_empty()
+    }
+}
+
+// Wraps an angle into (-PI, PI].
+fn get_signed_angle(angle: Angle) -> Angle {
+    let wrapped = angle.rem_euclid(PI * 2.0);
+
+    if wrapped > PI {
+        wrapped - PI * 2.0
+    } else {
+        wrapped
+    }
+}
+
+fn get_rms_error(candidate: Point2, placed: &[(usize, Point2)], distances: &[f64]) -> Option<f64> {
+    let errors: Vec<f64> = placed
+        .iter()
+        .filter(|(j, _)| distances[*j] > 0.0)
+        .map(|(j, p)| (candidate.distance(p) - distances[*j]).powi(2))
+        .collect();
+
+    if errors.is_empty() {
+        return None;
+    }
+
+    Some((errors.iter().sum::<f64>() / errors.len() as f64).sqrt())
+}
+
+// Which way the frame should be turned to agree with the hint: Some(true) to mirror it, Some(false) to keep it, None when the hint can't say.
+fn get_hinted_mirror(coordinates: &PolarCoordinates, hint: &HandednessHint, tolerance: f64) -> Option<bool> {
+    let get_angle = |id: &Identity| {
+        if *id == coordinates.origin {
+            return None;
+        }
+
+        coordinates.get(id).filter(|r| r.radius > tolerance).map(|r| r.angle)
+    };
+
+    match hint {
+        HandednessHint::Compass { first, second } => {
+            // Compass bearings turn clockwise and frame angles turn counterclockwise, so the same turn has opposite signs.
+            let compass_turn = -get_signed_angle(second.1 - first.1);
+            let frame_turn = get_signed_angle(get_angle(&second.0)? - get_angle(&first.0)?);
+
+            if compass_turn.sin().abs() < 1e-6 || frame_turn.sin().abs() < 1e-6 {
+                return None;
+            }
+
+            Some(compass_turn.signum() != frame_turn.signum())
+        },
+        HandednessHint::Anchor(anchor) => {
+            let known = anchor.angle.sin();
+            let solved = get_angle(&anchor.id)?.sin();
+
+            if known.abs() < 1e-6 || solved.abs() < 1e-6 {
+                return None;
+            }
+
+            Some(known.signum() != solved.signum())
+        },
+    }
+}
+
+// Takes coordinates straight out of the law of cosines (every angle in [0, PI] from the origin -> calibration line) and picks a side for each node.  references and distances are in the same order, with the origin first, and a distance of 0.0 means the pair wasn't measured.
+pub fn resolve_mirror(coordinates: &PolarCoordinates, references: &[Identity], distances: &[Vec<f64>], hint: Option<&HandednessHint>, config: &MirrorConfig) -> MirrorResolution {
+    let mut output = PolarCoordinates::new(coordinates.origin.clone());
+    let mut ambiguous = vec![];
+
+    // Everything already pinned down, by index into references.  The origin and anything on the calibration line are the same point on either side, so they are placed up front.
+    let mut placed: Vec<(usize, Point2)> = vec![];
+    let mut pending: Vec<(usize, &Radial)> = vec![];
+
+    for (i, id) in references.iter().enumerate() {
+        if *id == coordinates.origin {
+            placed.push((i, Point2::origin()));
+            continue;
+        }
+
+        let radial = match coordinates.get(id) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        if (radial.radius * radial.angle.sin()).abs() <= config.tolerance {
+            placed.push((i, Point2::from(radial)));
+            output.add_radial(radial.clone());
+        } else {
+            pending.push((i, radial));
+        }
+    }
+
+    // Nodes furthest off the line go first, since they have the most to say about where the rest go.
+    pending.sort_by(|a, b| (b.1.radius * b.1.angle.sin()).abs().total_cmp(&(a.1.radius * a.1.angle.sin()).abs()));
+
+    for (n, (i, radial)) in pending.iter().enumerate() {
+        let positive = Point2::from(*radial);
+        let negative = Point2::new(positive.x, -positive.y);
+
+        // The first node off the line sets the handedness of the frame, which distances can't decide.
+        let chosen = if n == 0 {
+            positive
+        } else {
+            match (get_rms_error(positive, &placed, &distances[*i]), get_rms_error(negative, &placed, &distances[*i])) {
+                (Some(pos_error), Some(neg_error)) => {
+                    if (pos_error - neg_error).abs() < config.tolerance {
+                        ambiguous.push(radial.id.clone());
+                    }
+
+                    if neg_error < pos_error { negative } else { positive }
+                },
+                _ => {
+                    ambiguous.push(radial.id.clone());
+                    positive
+                },
+            }
+        };
+
+        placed.push((*i, chosen));
+        output.add_radial(chosen.to_radial(radial.id.clone()));
+    }
+
+    let hinted_mirror = hint.and_then(|h| get_hinted_mirror(&output, h, config.tolerance));
+    let mirrored = hinted_mirror == Some(true);
+
+    if mirrored {
+        for radial in output.radials.values_mut() {
+            radial.angle = (-radial.angle).rem_euclid(PI * 2.0);
+        }
+    }
+
+    MirrorResolution {
+        coordinates: output,
+        ambiguous,
+        hinted: hinted_mirror.is_some(),
+        mirrored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds the unresolved law of cosines frame and the distance matrix for a layout, the same way get_coordinates does.
+    fn get_unresolved(layout: &[(&str, f64, f64)]) -> (PolarCoordinates, Vec<Identity>, Vec<Vec<f64>>) {
+        let references: Vec<Identity> = layout.iter().map(|(id, _, _)| (*id).into()).collect();
+        let points: Vec<Point2> = layout.iter().map(|(_, x, y)| Point2::new(*x, *y)).collect();
+        let distances: Vec<Vec<f64>> = points.iter().map(|a| points.iter().map(|b| a.distance(b)).collect()).collect();
+
+        let calibration: Vec<(Identity, f64)> = references.iter().cloned().zip(distances[0].clone()).collect();
+        let coordinates = PolarCoordinates::from_distances(references[0].clone(), references[1].clone(), vec![references[0].clone()], &calibration, &distances).unwrap();
+
+        (coordinates, references, distances)
+    }
+
+    #[test]
+    fn places_nodes_on_both_sides_of_the_line() {
+        let layout = [("O", 0.0, 0.0), ("A", 10.0, 0.0), ("B", 4.0, 6.0), ("C", 5.0, -3.0), ("D", -2.0, -5.0)];
+        let (coordinates, references, distances) = get_unresolved(&layout);
+
+        let resolution = resolve_mirror(&coordinates, &references, &distances, None, &MirrorConfig::default());
+        assert!(!resolution.is_ambiguous(), "{:?}", resolution);
+
+        for (id, x, y) in &layout[1..] {
+            let solved = Point2::from(&resolution.coordinates[&(*id).into()]);
+            assert!(solved.distance(&Point2::new(*x, *y)) < 1e-6, "{} at {:?}", id, solved);
+        }
+    }
+
+    #[test]
+    fn hint_pins_handedness() {
+        // The first node off the line (B) is put counterclockwise by default, so a layout with it below the line comes out mirrored without a hint.
+        let layout = [("O", 0.0, 0.0), ("A", 10.0, 0.0), ("B", 4.0, -6.0), ("C", 5.0, 3.0)];
+        let (coordinates, references, distances) = get_unresolved(&layout);
+
+        let unhinted = resolve_mirror(&coordinates, &references, &distances, None, &MirrorConfig::default());
+        assert!(!unhinted.hinted);
+        assert!(Point2::from(&unhinted.coordinates[&"B".into()]).y > 0.0);
+
+        let anchor = HandednessHint::Anchor(Point2::new(4.0, -6.0).to_radial("B".into()));
+        let hinted = resolve_mirror(&coordinates, &references, &distances, Some(&anchor), &MirrorConfig::default());
+        assert!(hinted.hinted && hinted.mirrored);
+        assert!(Point2::from(&hinted.coordinates[&"B".into()]).distance(&Point2::new(4.0, -6.0)) < 1e-6);
+        assert!(Point2::from(&hinted.coordinates[&"C".into()]).distance(&Point2::new(5.0, 3.0)) < 1e-6);
+
+        // Clockwise from north, A (east) is at 90 degrees and C (north of east) is at less than 90, which is a counterclockwise turn in the frame.
+        let compass = HandednessHint::Compass { first: ("A".into(), PI / 2.0), second: ("C".into(), PI / 3.0) };
+        let hinted = resolve_mirror(&coordinates, &references, &distances, Some(&compass), &MirrorConfig::default());
+        assert!(hinted.hinted && hinted.mirrored);
+        assert!(Point2::from(&hinted.coordinates[&"C".into()]).y > 0.0);
+    }
+}
@@ -1,10 +0,0 @@
-pub mod location;
-pub mod navigation;
-pub mod time_series;
-pub mod identity;
-pub mod filter;
-pub use location::*;
-pub use navigation::*;
-pub use time_series::*;
-pub use identity::*;
-pub use filter::*;
\ No newline at end of file
@@ -0,0 +1,304 @@
+// Locates a single agent against beacons whose positions have already been solved.
+// The ranges to the agent are fit with Levenberg-Marquardt, seeded from the
+// closed form linear least squares solution so it starts near the right basin.
+
+use crate::identity::Identity;
+use crate::location::MomentEdge;
+use crate::matrix::Matrix;
+use crate::point::Point2;
+use crate::polar::{PolarCoordinates, Radial};
+use crate::uncertainty::{self, Uncertainty};
+
+use std::collections::HashSet;
+
+// Two beacons leave a mirror ambiguity, so a position in the plane needs three.
+const MIN_BEACONS: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct MultilaterationConfig {
+    pub max_iterations: usize,
+    // Stop once a step moves the estimate less than this distance.
+    pub tolerance: f64,
+    pub initial_damping: f64,
+    // Standard deviation of the ranging error, used to size the uncertainty of the fix.
+    pub measurement_std: f64,
+}
+
+impl Default for MultilaterationConfig {
+    fn default() -> Self {
+        MultilaterationConfig {
+            max_iterations: 100,
+            tolerance: 1e-9,
+            initial_damping: 1e-3,
+            measurement_std: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvergenceReport {
+    pub iterations: usize,
+    pub converged: bool,
+    // The damping grew until no step could lower the cost, so the solver gave up where it was.  Not converged, and the position may be nowhere near a fit.
+    pub stalled: bool,
+    // Sum of squared residuals at the initial guess and at the solution.
+    pub initial_cost: f64,
+    pub final_cost: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Residual {
+    pub beacon: Identity,
+    pub measured: f64,
+    // Measured distance minus the distance from the solved position to the beacon.
+    pub residual: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Multilateration {
+    pub position: Radial,
+    pub residuals: Vec<Residual>,
+    pub report: ConvergenceReport,
+    // None when the beacons are all in line with the solved position.
+    pub uncertainty: Option<Uncertainty>,
+}
+
+impl Multilateration {
+    pub fn get_rms(&self) -> f64 {
+        if self.residuals.is_empty() {
+            return 0.0;
+        }
+
+        (self.residuals.iter().map(|r| r.residual.powi(2)).sum::<f64>() / self.residuals.len() as f64).sqrt()
+    }
+}
+
+struct Measurement {
+    beacon: Identity,
+    position: (f64, f64),
+    distance: f64,
+}
+
+fn get_beacon(beacons: &PolarCoordinates, id: &Identity) -> Option<(f64, f64)> {
+    if *id == beacons.origin {
+        return Some((0.0, 0.0));
+    }
+
+    beacons.get(id).map(|r| r.get_cartesian())
+}
+
+// Picks out the edges between the agent and a beacon with a known position.  Every edge is its own measurement, so repeated ranges to the same beacon are all used.
+fn get_measurements(agent: &Identity, beacons: &PolarCoordinates, edges: &[MomentEdge]) -> Vec<Measurement> {
+    edges
+        .iter()
+        .filter_map(|edge| {
+            let beacon = if edge.left == *agent {
+                &edge.right
+            } else if edge.right == *agent {
+                &edge.left
+            } else {
+                return None;
+            };
+
+            get_beacon(beacons, beacon).map(|position| Measurement {
+                beacon: beacon.clone(),
+                position,
+                distance: edge.distance,
+            })
+        })
+        .collect()
+}
+
+fn get_cost(measurements: &[Measurement], point: (f64, f64)) -> f64 {
+    measurements.iter().map(|m| (get_range(m.position, point) - m.distance).powi(2)).sum()
+}
+
+fn get_range(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+// Subtracting the first range equation from the others cancels the squared unknowns, leaving a linear system.  Falls back to the beacon centroid when the beacons are collinear.
+fn get_initial_guess(measurements: &[Measurement]) -> (f64, f64) {
+    let count = measurements.len() as f64;
+    let centroid = (
+        measurements.iter().map(|m| m.position.0).sum::<f64>() / count,
+        measurements.iter().map(|m| m.position.1).sum::<f64>() / count,
+    );
+
+    let first = &measurements[0];
+    let mut a = Matrix::zeros(measurements.len() - 1, 2);
+    let mut b = Matrix::zeros(measurements.len() - 1, 1);
+
+    for (i, m) in measurements[1..].iter().enumerate() {
+        a[(i, 0)] = 2.0 * (m.position.0 - first.position.0);
+        a[(i, 1)] = 2.0 * (m.position.1 - first.position.1);
+        b[(i, 0)] = first.distance.powi(2) - m.distance.powi(2) + m.position.0.powi(2) - first.position.0.powi(2) + m.position.1.powi(2) - first.position.1.powi(2);
+    }
+
+    let normal = &a.transpose() * &a;
+
+    match normal.inverse() {
+        Some(inverse) => {
+            let solution = &(&inverse * &a.transpose()) * &b;
+            (solution[(0, 0)], solution[(1, 0)])
+        },
+        None => centroid,
+    }
+}
+
+pub fn multilaterate(agent: &Identity, beacons: &PolarCoordinates, edges: &[MomentEdge], config: &MultilaterationConfig) -> Option<Multilateration> {
+    let measurements = get_measurements(agent, beacons, edges);
+    let distinct: HashSet<&Identity> = measurements.iter().map(|m| &m.beacon).collect();
+
+    if distinct.len() < MIN_BEACONS {
+        return None;
+    }
+
+    let mut point = get_initial_guess(&measurements);
+    let mut cost = get_cost(&measurements, point);
+    let initial_cost = cost;
+    let mut damping = config.initial_damping;
+    let mut iterations = 0;
+    let mut converged = false;
+    let mut stalled = false;
+
+    while iterations < config.max_iterations {
+        iterations += 1;
+
+        let mut jacobian = Matrix::zeros(measurements.len(), 2);
+        let mut residuals = Matrix::zeros(measurements.len(), 1);
+
+        for (i, m) in measurements.iter().enumerate() {
+            let range = get_range(m.position, point).max(1e-12);
+            jacobian[(i, 0)] = (point.0 - m.position.0) / range;
+            jacobian[(i, 1)] = (point.1 - m.position.1) / range;
+            residuals[(i, 0)] = range - m.distance;
+        }
+
+        let normal = &jacobian.transpose() * &jacobian;
+        let gradient = &jacobian.transpose() * &residuals;
+
+        let mut damped = normal.clone();
+        damped[(0, 0)] += damping * normal[(0, 0)].max(1e-12);
+        damped[(1, 1)] += damping * normal[(1, 1)].max(1e-12);
+
+        let step = match damped.inverse() {
+            Some(inverse) => (&inverse * &gradient).scale(-1.0),
+            None => break,
+        };
+
+        let candidate = (point.0 + step[(0, 0)], point.1 + step[(1, 0)]);
+        let candidate_cost = get_cost(&measurements, candidate);
+
+        if candidate_cost <= cost {
+            let moved = get_range(point, candidate);
+            point = candidate;
+            cost = candidate_cost;
+            damping = (damping / 10.0).max(1e-12);
+
+            if moved < config.tolerance {
+                converged = true;
+                break;
+            }
+        } else {
+            // At a true minimum no step lowers the cost, other than by float noise.  A gradient that flat means the fit is already where it should be.
+            let slope = (gradient[(0, 0)].powi(2) + gradient[(1, 0)].powi(2)).sqrt();
+
+            if slope < config.tolerance {
+                converged = true;
+                break;
+            }
+
+            damping *= 10.0;
+
+            // The step is so damped it can't move anymore, but that doesn't make this a fit.
+            if damping > 1e12 {
+                stalled = true;
+                break;
+            }
+        }
+    }
+
+    Some(Multilateration {
+        position: Point2::from(point).to_radial(agent.clone()),
+        residuals: measurements
+            .iter()
+            .map(|m| Residual {
+                beacon: m.beacon.clone(),
+                measured: m.distance,
+                residual: m.distance - get_range(m.position, point),
+            })
+            .collect(),
+        report: ConvergenceReport {
+            iterations,
+            converged,
+            stalled,
+            initial_cost,
+            final_cost: cost,
+        },
+        uncertainty: uncertainty::get_uncertainty(
+            Point2::from(point),
+            &measurements.iter().map(|m| Point2::from(m.position)).collect::<Vec<Point2>>(),
+            config.measurement_std.powi(2),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use std::time::SystemTime;
+
+    fn get_beacons() -> PolarCoordinates {
+        let mut beacons = PolarCoordinates::new("O".into());
+        beacons.add_radial(Radial { id: "B".into(), radius: 10.0, angle: 0.0 });
+        beacons.add_radial(Radial { id: "C".into(), radius: 10.0, angle: PI / 2.0 });
+        beacons.add_radial(Radial { id: "D".into(), radius: 200.0_f64.sqrt(), angle: PI / 4.0 });
+        beacons
+    }
+
+    fn get_edges(target: (f64, f64), noise: &[f64]) -> Vec<MomentEdge> {
+        [("O", (0.0, 0.0)), ("B", (10.0, 0.0)), ("C", (0.0, 10.0)), ("D", (10.0, 10.0))]
+            .iter()
+            .zip(noise)
+            .map(|((id, position), n)| MomentEdge::new("A".into(), (*id).into(), get_range(*position, target) + n, SystemTime::now()))
+            .collect()
+    }
+
+    #[test]
+    fn solves_exact_ranges() {
+        let result = multilaterate(&"A".into(), &get_beacons(), &get_edges((3.0, 7.0), &[0.0; 4]), &MultilaterationConfig::default()).unwrap();
+        let (x, y) = result.position.get_cartesian();
+
+        assert!(result.report.converged && !result.report.stalled);
+        assert!((x - 3.0).abs() < 1e-6 && (y - 7.0).abs() < 1e-6, "{:?}", result);
+        assert!(result.get_rms() < 1e-6);
+        assert!(result.uncertainty.unwrap().hdop < 2.0);
+    }
+
+    #[test]
+    fn fits_noisy_ranges() {
+        let result = multilaterate(&"A".into(), &get_beacons(), &get_edges((-2.0, 4.0), &[0.1, -0.1, 0.05, -0.05]), &MultilaterationConfig::default()).unwrap();
+        let (x, y) = result.position.get_cartesian();
+
+        assert!(result.report.final_cost <= result.report.initial_cost);
+        assert!(result.report.converged && !result.report.stalled, "{:?}", result.report);
+        assert!((x + 2.0).abs() < 0.2 && (y - 4.0).abs() < 0.2, "{:?}", result);
+        assert_eq!(result.residuals.len(), 4);
+    }
+
+    #[test]
+    fn garbage_ranges_stall_instead_of_converging() {
+        let result = multilaterate(&"A".into(), &get_beacons(), &get_edges((3.0, 7.0), &[0.0, f64::NAN, 0.0, 0.0]), &MultilaterationConfig::default()).unwrap();
+
+        assert!(result.report.stalled);
+        assert!(!result.report.converged);
+    }
+
+    #[test]
+    fn needs_three_beacons() {
+        let edges = get_edges((3.0, 7.0), &[0.0; 2]);
+        assert!(multilaterate(&"A".into(), &get_beacons(), &edges, &MultilaterationConfig::default()).is_none());
+    }
+}
@@ -1,32 +1,184 @@
-use crate::network::identity::Identity;
-use super::location::{Location, DistanceEdge};
+use crate::error::{Error, Result};
+use crate::identity::Identity;
+use crate::filter::DistanceFilter;
+use crate::point::Point2;
+use crate::polar::{PolarCoordinates, Radial};
+use crate::uncertainty::{self, Uncertainty};
+use super::location::{Location, MomentEdge, DistanceGraph};
 use super::time_series::LocationTimeSeries;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
+// The minimum number of nodes needed before the distance graph can be solved.  The origin and calibration node define the frame, and at least one more node is required to pick a handedness for the angles.
+const MIN_SOLVABLE_NODES: usize = 3;
+
+#[derive(Debug)]
+pub struct NodeLocation {
+    pub position: Radial,
+    pub location: Location,
+    // From the spread of the ranges to each neighbour and where those neighbours were solved.  None when the neighbours can't pin the node down.
+    pub uncertainty: Option<Uncertainty>,
+}
 
 pub struct Navigator {
-    pub id: String,
-    pub archive: Vec<DistanceEdge>,
+    pub id: Identity,
+    pub archive: DistanceGraph,
     pub cache: LocationTimeSeries,
     pub nodes: HashSet<Identity>
 }
 
 impl Navigator {
-    pub fn add(&mut self, edge: DistanceEdge) {
+    pub fn new(id: Identity) -> Navigator {
+        Navigator {
+            id,
+            archive: DistanceGraph::new(),
+            cache: LocationTimeSeries::new(),
+            nodes: HashSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, edge: MomentEdge) {
+        self.nodes.insert(edge.left.clone());
+        self.nodes.insert(edge.right.clone());
+        self.archive.add(edge);
+    }
+
+    pub fn extend(&mut self, edges: Vec<MomentEdge>) {
+        for edge in edges {
+            self.add(edge);
+        }
+    }
+
+    // Returns the nodes in a stable order.  The first node becomes the origin of the solved frame, so sorting keeps the frame the same between calls as long as the node set doesn't change.
+    pub fn get_nodes(&self) -> Vec<Identity> {
+        let mut nodes: Vec<Identity> = self.nodes.iter().cloned().collect();
+        nodes.sort();
+        nodes
+    }
+
+    // Fails with UnknownIdentity for a node no edge has mentioned, InsufficientData until there are enough nodes to solve, and whatever the solve fails with otherwise.  A node the solve couldn't place is DegenerateGeometry.
+    pub fn get_node_location(&self, node: &Identity, filter: &dyn DistanceFilter) -> Result<NodeLocation> {
+        if !self.nodes.contains(node) {
+            return Err(Error::UnknownIdentity(node.clone()));
+        }
+
+        if self.nodes.len() < MIN_SOLVABLE_NODES {
+            return Err(Error::InsufficientData { needed: MIN_SOLVABLE_NODES, found: self.nodes.len() });
+        }
+
+        let coordinates = self.archive.get_position_graph(&self.get_nodes(), filter)?;
+
+        // The origin isn't stored as a radial since it sits at the center of the frame.
+        let position = if coordinates.origin == *node {
+            Radial::empty(node.clone())
+        } else {
+            coordinates.get(node).cloned().ok_or_else(|| Error::DegenerateGeometry(format!("{} couldn't be placed against the other nodes", node)))?
+        };
+
+        let location = Location::from_distances(node.clone(), self.archive.get_node_graph(node.clone()).edges());
+        let uncertainty = get_uncertainty(&position, &location, &coordinates);
+
+        Ok(NodeLocation {
+            position,
+            location,
+            uncertainty,
+        })
+    }
+}
+
+// Pools the variance of the repeated ranges to every neighbour that was placed, and sizes it by the geometry of those neighbours around the node.
+fn get_uncertainty(position: &Radial, location: &Location, coordinates: &PolarCoordinates) -> Option<Uncertainty> {
+    let mut samples: HashMap<Identity, Vec<f64>> = HashMap::new();
 
+    for edge in location.distances.iter() {
+        let neighbour = if edge.left == location.node { &edge.right } else { &edge.left };
+        samples.entry(neighbour.clone()).or_default().push(edge.distance);
     }
 
-    pub fn extend(&mut self, edges: Vec<DistanceEdge>) {
+    let mut beacons = vec![];
+    let mut squared_error = 0.0;
+    let mut degrees_of_freedom = 0;
 
+    for (neighbour, ranges) in &samples {
+        let placed = if *neighbour == coordinates.origin {
+            Some(Point2::origin())
+        } else {
+            coordinates.get(neighbour).map(Point2::from)
+        };
+
+        if let Some(point) = placed {
+            let mean = ranges.iter().sum::<f64>() / ranges.len() as f64;
+            squared_error += ranges.iter().map(|r| (r - mean).powi(2)).sum::<f64>();
+            degrees_of_freedom += ranges.len() - 1;
+            beacons.push(point);
+        }
+    }
+
+    // A single range per neighbour says nothing about how noisy the ranges are, and a zero variance would make any fix look perfect.
+    if degrees_of_freedom == 0 {
+        return None;
+    }
+
+    uncertainty::get_uncertainty(Point2::from(position), &beacons, squared_error / degrees_of_freedom as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MedianFilter;
+    use std::time::SystemTime;
+
+    fn get_navigator(samples: usize) -> Navigator {
+        let layout = [("A", 0.0, 0.0), ("B", 10.0, 0.0), ("C", 0.0, 10.0), ("D", 10.0, 10.0)];
+        let mut navigator = Navigator::new("N".into());
+
+        for (i, (a, ax, ay)) in layout.iter().enumerate() {
+            for (b, bx, by) in &layout[i + 1..] {
+                let distance = Point2::new(*ax, *ay).distance(&Point2::new(*bx, *by));
+
+                for n in 0..samples {
+                    navigator.add(MomentEdge::new((*a).into(), (*b).into(), distance + 0.05 * n as f64, SystemTime::now()));
+                }
+            }
+        }
+
+        navigator
     }
 
-    pub fn get_node_location(&self, node: Identity) -> Location {
-        let mut distances: Vec<DistanceEdge> = vec![];
+    #[test]
+    fn locates_nodes_from_added_edges() {
+        let truth = [("A", Point2::new(0.0, 0.0)), ("B", Point2::new(10.0, 0.0)), ("C", Point2::new(0.0, 10.0)), ("D", Point2::new(10.0, 10.0))];
+        let edge = |a: usize, b: usize| MomentEdge::new(truth[a].0.into(), truth[b].0.into(), truth[a].1.distance(&truth[b].1), SystemTime::now());
+        let mut navigator = Navigator::new("N".into());
+
+        navigator.add(edge(0, 1));
+        assert_eq!(navigator.get_nodes(), vec![Identity::from("A"), Identity::from("B")]);
+        assert_eq!(navigator.get_node_location(&"A".into(), &MedianFilter).unwrap_err(), Error::InsufficientData { needed: 3, found: 2 });
+
+        navigator.extend(vec![edge(0, 2), edge(0, 3), edge(1, 2), edge(1, 3), edge(2, 3)]);
+        assert_eq!(navigator.get_nodes().len(), 4);
+
+        // A is the origin and B sets the 0 angle, so the solved frame is the ground truth, possibly mirrored across the x axis.
+        let mirror = Point2::from(&navigator.get_node_location(&"C".into(), &MedianFilter).unwrap().position).y.signum();
 
-        // for d in self.get_node_snapshot(node) {
+        for (id, point) in truth {
+            let located = navigator.get_node_location(&id.into(), &MedianFilter).unwrap();
+            let solved = Point2::from(&located.position);
+            assert!(solved.distance(&Point2::new(point.x, point.y * mirror)) < 1e-9, "{} at {:?}", id, solved);
 
-        // }
+            // Every edge the node is on, and nothing else.
+            assert_eq!(located.location.distances.len(), 3);
+            assert!(located.location.distances.iter().all(|e| e.left.as_ref() == id || e.right.as_ref() == id));
+        }
 
-        Location::from_distances(node.clone(), distances)
+        assert_eq!(navigator.get_node_location(&"Z".into(), &MedianFilter).unwrap_err(), Error::UnknownIdentity("Z".into()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn uncertainty_needs_repeated_ranges() {
+        let single = get_navigator(1).get_node_location(&"D".into(), &MedianFilter).unwrap();
+        assert!(single.uncertainty.is_none());
+
+        let repeated = get_navigator(3).get_node_location(&"D".into(), &MedianFilter).unwrap();
+        assert!(repeated.uncertainty.unwrap().hdop.is_finite());
+    }
+}
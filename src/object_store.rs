@@ -0,0 +1,350 @@
+// Local filesystem archive for location history.  Batches are written under the
+// time_series::get_path layout:
+//    <root>/YYYY/MM/DD/hh/mm/ss/mmm/<iiinnn>.<kind>
+//
+// The leaf directory is the millisecond a batch was recorded at, and the file
+// name holds the remaining micro and nanoseconds so a time range can be
+// rebuilt from the path alone without opening every file.
+
+use crate::identity::Identity;
+use crate::location::{Location, MomentEdge};
+use crate::time_series::get_path;
+
+use datetime::{LocalDate, LocalDateTime, LocalTime, Month};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// YYYY/MM/DD/hh/mm/ss/mmm
+const PATH_DEPTH: usize = 7;
+
+const EDGE_EXTENSION: &str = "edges";
+const LOCATION_EXTENSION: &str = "locations";
+
+pub struct ObjectStore {
+    pub root: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredObject {
+    pub path: PathBuf,
+    pub timestamp: SystemTime,
+}
+
+impl ObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> ObjectStore {
+        ObjectStore {
+            root: root.into(),
+        }
+    }
+
+    pub fn put_edges(&self, timestamp: SystemTime, edges: &[MomentEdge]) -> io::Result<PathBuf> {
+        let mut data = String::new();
+
+        for edge in edges {
+            write_edge(&mut data, edge);
+        }
+
+        self.append(timestamp, EDGE_EXTENSION, data)
+    }
+
+    pub fn put_locations(&self, timestamp: SystemTime, locations: &[Location]) -> io::Result<PathBuf> {
+        let mut data = String::new();
+
+        for location in locations {
+            write_identity(&mut data, &location.node);
+            data.push_str(&format!(" {}\n", location.distances.len()));
+
+            for edge in location.distances.iter() {
+                write_edge(&mut data, edge);
+            }
+        }
+
+        self.append(timestamp, LOCATION_EXTENSION, data)
+    }
+
+    pub fn get_edges(&self, start: SystemTime, end: SystemTime) -> io::Result<Vec<MomentEdge>> {
+        let mut edges: Vec<MomentEdge> = vec![];
+
+        for object in self.list(start, end, EDGE_EXTENSION)? {
+            let data = fs::read_to_string(&object.path)?;
+            let mut reader = Reader::new(&data);
+
+            while !reader.is_empty() {
+                edges.push(reader.read_edge()?);
+            }
+        }
+
+        Ok(edges)
+    }
+
+    pub fn get_locations(&self, start: SystemTime, end: SystemTime) -> io::Result<Vec<Location>> {
+        let mut locations: Vec<Location> = vec![];
+
+        for object in self.list(start, end, LOCATION_EXTENSION)? {
+            let data = fs::read_to_string(&object.path)?;
+            let mut reader = Reader::new(&data);
+
+            while !reader.is_empty() {
+                let node = reader.read_identity()?;
+                let count = reader.read_number::<usize>()?;
+                let mut distances: Vec<MomentEdge> = Vec::with_capacity(count);
+
+                for _ in 0..count {
+                    distances.push(reader.read_edge()?);
+                }
+
+                locations.push(Location::from_distances(node, distances));
+            }
+        }
+
+        Ok(locations)
+    }
+
+    // Lists every stored batch of the given kind with a timestamp in [start, end), oldest first.
+    pub fn list(&self, start: SystemTime, end: SystemTime, extension: &str) -> io::Result<Vec<StoredObject>> {
+        let mut objects: Vec<StoredObject> = vec![];
+
+        for (directory, directory_time) in self.get_directories()? {
+            // Nothing in a millisecond directory can fall inside the window if the whole millisecond is outside of it.
+            if directory_time >= end || directory_time + Duration::from_millis(1) <= start {
+                continue;
+            }
+
+            for entry in fs::read_dir(&directory)? {
+                let path = entry?.path();
+
+                if path.extension().and_then(|e| e.to_str()) != Some(extension) {
+                    continue;
+                }
+
+                let sub_millis = match path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                    Some(nanos) => Duration::from_nanos(nanos),
+                    None => continue,
+                };
+
+                let timestamp = directory_time + sub_millis;
+
+                if timestamp >= start && timestamp < end {
+                    objects.push(StoredObject { path, timestamp });
+                }
+            }
+        }
+
+        objects.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.path.cmp(&b.path)));
+        Ok(objects)
+    }
+
+    // Removes every millisecond directory that is entirely older than `now - max_age`, then any parent directories left empty.  Returns the number of millisecond directories removed.
+    pub fn prune(&self, now: SystemTime, max_age: Duration) -> io::Result<usize> {
+        let cutoff = match now.checked_sub(max_age) {
+            Some(cutoff) => cutoff,
+            None => return Ok(0),
+        };
+
+        let mut removed = 0;
+
+        for (directory, directory_time) in self.get_directories()? {
+            if directory_time + Duration::from_millis(1) > cutoff {
+                continue;
+            }
+
+            fs::remove_dir_all(&directory)?;
+            removed += 1;
+
+            let mut parent = directory.parent();
+
+            while let Some(p) = parent {
+                if p == self.root || fs::read_dir(p)?.next().is_some() {
+                    break;
+                }
+
+                fs::remove_dir(p)?;
+                parent = p.parent();
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn append(&self, timestamp: SystemTime, extension: &str, data: String) -> io::Result<PathBuf> {
+        let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).map_err(|_| invalid_data("timestamp is before the unix epoch"))?;
+        let datetime = LocalDateTime::at_ms(since_epoch.as_secs() as i64, since_epoch.subsec_millis() as i16);
+
+        let directory = self.root.join(&*get_path(datetime));
+        fs::create_dir_all(&directory)?;
+
+        let path = directory.join(format!("{:06}.{}", since_epoch.subsec_nanos() % 1_000_000, extension));
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(data.as_bytes())?;
+
+        Ok(path)
+    }
+
+    // Walks the tree down to the millisecond directories, returning each one with the time it represents.  Anything that doesn't parse as part of the layout is ignored.
+    fn get_directories(&self) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+        let mut output: Vec<(PathBuf, SystemTime)> = vec![];
+
+        if !self.root.is_dir() {
+            return Ok(output);
+        }
+
+        let mut pending: Vec<(PathBuf, Vec<i64>)> = vec![(self.root.clone(), vec![])];
+
+        while let Some((directory, pieces)) = pending.pop() {
+            if pieces.len() == PATH_DEPTH {
+                if let Some(time) = get_path_time(&pieces) {
+                    output.push((directory, time));
+                }
+
+                continue;
+            }
+
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+
+                if let Some(piece) = entry.file_name().to_str().and_then(|n| n.parse::<i64>().ok()) {
+                    let mut next = pieces.clone();
+                    next.push(piece);
+                    pending.push((entry.path(), next));
+                }
+            }
+        }
+
+        output.sort_by_key(|a| a.1);
+        Ok(output)
+    }
+}
+
+fn get_path_time(pieces: &[i64]) -> Option<SystemTime> {
+    let month = Month::from_one(pieces[1] as i8).ok()?;
+    let date = LocalDate::ymd(pieces[0], month, pieces[2] as i8).ok()?;
+    let time = LocalTime::hms_ms(pieces[3] as i8, pieces[4] as i8, pieces[5] as i8, pieces[6] as i16).ok()?;
+    let instant = LocalDateTime::new(date, time).to_instant();
+
+    if instant.seconds() < 0 {
+        return None;
+    }
+
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(instant.seconds() as u64) + Duration::from_millis(instant.milliseconds() as u64))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Identities are written length prefixed (`<len>:<id>`) so they can safely contain spaces or newlines.
+fn write_identity(data: &mut String, id: &Identity) {
+    data.push_str(&format!("{}:{}", id.len(), id));
+}
+
+fn write_edge(data: &mut String, edge: &MomentEdge) {
+    let nanos = edge.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_nanos();
+
+    write_identity(data, &edge.left);
+    data.push(' ');
+    write_identity(data, &edge.right);
+    data.push_str(&format!(" {} {}\n", edge.distance, nanos));
+}
+
+struct Reader<'a> {
+    data: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a str) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.trim_start().is_empty()
+    }
+
+    fn read_identity(&mut self) -> io::Result<Identity> {
+        self.data = self.data.trim_start();
+
+        let split = self.data.find(':').ok_or_else(|| invalid_data("missing identity length"))?;
+        let length = self.data[..split].parse::<usize>().map_err(|_| invalid_data("invalid identity length"))?;
+        let rest = &self.data[(split + 1)..];
+        let id = rest.get(..length).ok_or_else(|| invalid_data("truncated identity"))?;
+
+        self.data = &rest[length..];
+        Ok(id.into())
+    }
+
+    fn read_number<T: std::str::FromStr>(&mut self) -> io::Result<T> {
+        self.data = self.data.trim_start();
+
+        let end = self.data.find(char::is_whitespace).unwrap_or(self.data.len());
+        let value = self.data[..end].parse::<T>().map_err(|_| invalid_data("invalid number"))?;
+
+        self.data = &self.data[end..];
+        Ok(value)
+    }
+
+    fn read_edge(&mut self) -> io::Result<MomentEdge> {
+        let left = self.read_identity()?;
+        let right = self.read_identity()?;
+        let distance = self.read_number::<f64>()?;
+        let nanos = self.read_number::<u128>()?;
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32);
+
+        Ok(MomentEdge::new(left, right, distance, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + millis)
+    }
+
+    fn temp_store(name: &str) -> ObjectStore {
+        let root = std::env::temp_dir().join(format!("swarmy-object-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        ObjectStore::new(root)
+    }
+
+    #[test]
+    fn edges_round_trip_through_time_range() {
+        let store = temp_store("edges");
+        let edge = MomentEdge::new("a b".into(), "c\nd".into(), 12.5, at(5) + Duration::from_nanos(1_234));
+
+        store.put_edges(at(0), &[MomentEdge::new("A".into(), "B".into(), 1.0, at(0))]).unwrap();
+        store.put_edges(at(5), std::slice::from_ref(&edge)).unwrap();
+        store.put_edges(at(1_500), &[MomentEdge::new("A".into(), "B".into(), 3.0, at(1_500))]).unwrap();
+
+        let edges = store.get_edges(at(1), at(1_500)).unwrap();
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].left, edge.left);
+        assert_eq!(edges[0].right, edge.right);
+        assert_eq!(edges[0].distance, edge.distance);
+        assert_eq!(edges[0].timestamp, edge.timestamp);
+
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn prune_removes_old_directories() {
+        let store = temp_store("prune");
+        let location = Location::from_node("A".into(), vec![("B".into(), 2.0, at(0))]);
+
+        store.put_locations(at(0), std::slice::from_ref(&location)).unwrap();
+        store.put_locations(at(10_000), &[location]).unwrap();
+
+        assert_eq!(store.prune(at(10_000), Duration::from_secs(5)).unwrap(), 1);
+
+        let remaining = store.get_locations(at(0), at(20_000)).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].distances.len(), 1);
+
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+// Particle filter localization from ranges to beacons at known positions.  Unlike
+// the Kalman tracker this keeps a cloud of candidate positions, so it can hold on
+// to more than one answer (e.g. both sides of a mirror ambiguity with only two
+// beacons in range) until later measurements rule one out.
+
+use crate::beacon::beacon::BeaconSignal;
+use crate::identity::Identity;
+use crate::point::Point2;
+use crate::polar::{PolarCoordinates, Radial};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampling {
+    // Draws every particle independently in proportion to its weight.
+    Multinomial,
+    // One random offset, then evenly spaced draws.  Lowest variance of the three.
+    Systematic,
+    // One random draw inside each of N evenly spaced strata.
+    Stratified,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParticleFilterConfig {
+    pub particles: usize,
+    pub resampling: Resampling,
+    // Standard deviation of the ranging error.
+    pub measurement_std: f64,
+    // Standard deviation of the random walk applied to every particle before an update.
+    pub motion_std: f64,
+    // Resample when the effective number of particles drops below this fraction of the total.
+    pub resample_threshold: f64,
+    pub seed: u64,
+}
+
+impl Default for ParticleFilterConfig {
+    fn default() -> Self {
+        ParticleFilterConfig {
+            particles: 1_000,
+            resampling: Resampling::Systematic,
+            measurement_std: 0.5,
+            motion_std: 0.1,
+            resample_threshold: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub x: f64,
+    pub y: f64,
+    pub weight: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParticleEstimate {
+    // Weighted mean of the particles.
+    pub position: Radial,
+    // The single heaviest particle.  When the cloud is split between two modes the mean can sit between them, so this is the better guess in that case.
+    pub best: Radial,
+    // Root mean square distance of the particles from the mean.
+    pub spread: f64,
+    pub effective_particles: f64,
+}
+
+pub struct ParticleFilter {
+    pub id: Identity,
+    pub config: ParticleFilterConfig,
+    pub beacons: PolarCoordinates,
+    particles: Vec<Particle>,
+    rng: StdRng,
+}
+
+impl ParticleFilter {
+    pub fn new(id: Identity, beacons: PolarCoordinates, config: ParticleFilterConfig) -> ParticleFilter {
+        let rng = StdRng::seed_from_u64(config.seed);
+
+        ParticleFilter {
+            id,
+            config,
+            beacons,
+            particles: vec![],
+            rng,
+        }
+    }
+
+    pub fn get_particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    fn get_beacon(&self, id: &Identity) -> Option<(f64, f64)> {
+        if *id == self.beacons.origin {
+            return Some((0.0, 0.0));
+        }
+
+        self.beacons.get(id).map(|r| r.get_cartesian())
+    }
+
+    // Spreads the particles evenly over a box holding every beacon, padded by the longest range heard, since the agent can't be further than that from the beacon that heard it.
+    fn initialize(&mut self, measurements: &[((f64, f64), f64)]) {
+        let reach = measurements.iter().map(|m| m.1).fold(0.0, f64::max);
+        let min_x = measurements.iter().map(|m| m.0.0).fold(f64::INFINITY, f64::min) - reach;
+        let max_x = measurements.iter().map(|m| m.0.0).fold(f64::NEG_INFINITY, f64::max) + reach;
+        let min_y = measurements.iter().map(|m| m.0.1).fold(f64::INFINITY, f64::min) - reach;
+        let max_y = measurements.iter().map(|m| m.0.1).fold(f64::NEG_INFINITY, f64::max) + reach;
+
+        let count = self.config.particles.max(1);
+        let weight = 1.0 / count as f64;
+
+        self.particles = (0..count)
+            .map(|_| Particle {
+                x: min_x + self.rng.gen::<f64>() * (max_x - min_x),
+                y: min_y + self.rng.gen::<f64>() * (max_y - min_y),
+                weight,
+            })
+            .collect();
+    }
+
+    // Folds in a batch of ranges heard at the same moment.  Signals from beacons without a known position are skipped.  Returns None when none of the signals could be used.
+    pub fn update(&mut self, signals: &[BeaconSignal]) -> Option<ParticleEstimate> {
+        let measurements: Vec<((f64, f64), f64)> = signals
+            .iter()
+            .filter_map(|s| self.get_beacon(&s.id).map(|b| (b, s.distance)))
+            .collect();
+
+        if measurements.is_empty() {
+            return None;
+        }
+
+        if self.particles.is_empty() {
+            self.initialize(&measurements);
+        } else {
+            self.predict();
+        }
+
+        let sigma = self.config.measurement_std.max(1e-9);
+
+        // Weights are built in log space and shifted by the largest one, otherwise a handful of confident ranges underflow every weight to 0.0.
+        let log_weights: Vec<f64> = self
+            .particles
+            .iter()
+            .map(|p| {
+                p.weight.ln() + measurements.iter().map(|((bx, by), range)| {
+                    let predicted = ((p.x - bx).powi(2) + (p.y - by).powi(2)).sqrt();
+                    -0.5 * ((range - predicted) / sigma).powi(2)
+                }).sum::<f64>()
+            })
+            .collect();
+
+        let max_log = log_weights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        for (particle, log_weight) in self.particles.iter_mut().zip(&log_weights) {
+            particle.weight = (log_weight - max_log).exp();
+        }
+
+        self.normalize();
+
+        let estimate = self.get_estimate();
+
+        if estimate.effective_particles < self.config.resample_threshold * self.particles.len() as f64 {
+            self.resample();
+        }
+
+        Some(estimate)
+    }
+
+    fn predict(&mut self) {
+        let motion_std = self.config.motion_std;
+
+        if motion_std <= 0.0 {
+            return;
+        }
+
+        for i in 0..self.particles.len() {
+            self.particles[i].x += get_gaussian(&mut self.rng) * motion_std;
+            self.particles[i].y += get_gaussian(&mut self.rng) * motion_std;
+        }
+    }
+
+    fn normalize(&mut self) {
+        let total: f64 = self.particles.iter().map(|p| p.weight).sum();
+
+        if total <= 0.0 || !total.is_finite() {
+            let weight = 1.0 / self.particles.len() as f64;
+            self.particles.iter_mut().for_each(|p| p.weight = weight);
+            return;
+        }
+
+        self.particles.iter_mut().for_each(|p| p.weight /= total);
+    }
+
+    pub fn get_estimate(&self) -> ParticleEstimate {
+        let mean_x: f64 = self.particles.iter().map(|p| p.x * p.weight).sum();
+        let mean_y: f64 = self.particles.iter().map(|p| p.y * p.weight).sum();
+        let variance: f64 = self.particles.iter().map(|p| p.weight * ((p.x - mean_x).powi(2) + (p.y - mean_y).powi(2))).sum();
+        let squared_weights: f64 = self.particles.iter().map(|p| p.weight.powi(2)).sum();
+
+        let best = self
+            .particles
+            .iter()
+            .max_by(|a, b| a.weight.total_cmp(&b.weight))
+            .map_or((mean_x, mean_y), |p| (p.x, p.y));
+
+        ParticleEstimate {
+            position: Point2::new(mean_x, mean_y).to_radial(self.id.clone()),
+            best: Point2::from(best).to_radial(self.id.clone()),
+            spread: variance.sqrt(),
+            effective_particles: if squared_weights > 0.0 { 1.0 / squared_weights } else { 0.0 },
+        }
+    }
+
+    fn resample(&mut self) {
+        let count = self.particles.len();
+        let step = 1.0 / count as f64;
+
+        let draws: Vec<f64> = match self.config.resampling {
+            Resampling::Multinomial => {
+                let mut draws: Vec<f64> = (0..count).map(|_| self.rng.gen::<f64>()).collect();
+                draws.sort_by(|a, b| a.total_cmp(b));
+                draws
+            },
+            Resampling::Systematic => {
+                let offset = self.rng.gen::<f64>() * step;
+                (0..count).map(|i| offset + i as f64 * step).collect()
+            },
+            Resampling::Stratified => {
+                (0..count).map(|i| (i as f64 + self.rng.gen::<f64>()) * step).collect()
+            },
+        };
+
+        // Walk the cumulative weights once, since the draws are sorted.
+        let mut resampled: Vec<Particle> = Vec::with_capacity(count);
+        let mut cumulative = self.particles[0].weight;
+        let mut idx = 0;
+
+        for draw in draws {
+            while draw > cumulative && idx < count - 1 {
+                idx += 1;
+                cumulative += self.particles[idx].weight;
+            }
+
+            resampled.push(Particle { weight: step, ..self.particles[idx] });
+        }
+
+        self.particles = resampled;
+    }
+}
+
+// Standard normal sample using the Box-Muller transform.
+pub(crate) fn get_gaussian(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.gen::<f64>().max(f64::MIN_POSITIVE);
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn get_beacons() -> PolarCoordinates {
+        let mut beacons = PolarCoordinates::new("O".into());
+        beacons.add_radial(Radial { id: "B".into(), radius: 10.0, angle: 0.0 });
+        beacons.add_radial(Radial { id: "C".into(), radius: 10.0, angle: PI / 2.0 });
+        beacons
+    }
+
+    fn get_signals(target: (f64, f64)) -> Vec<BeaconSignal> {
+        [("O", (0.0, 0.0)), ("B", (10.0, 0.0)), ("C", (0.0, 10.0))]
+            .iter()
+            .map(|(id, (x, y))| BeaconSignal {
+                id: (*id).into(),
+                distance: ((target.0 - x).powi(2) + (target.1 - y).powi(2)).sqrt(),
+                timestamp: SystemTime::UNIX_EPOCH,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converges_on_target() {
+        let mut filter = ParticleFilter::new("A".into(), get_beacons(), ParticleFilterConfig::default());
+        let signals = get_signals((3.0, 4.0));
+        let mut estimate = None;
+
+        for _ in 0..10 {
+            estimate = filter.update(&signals);
+        }
+
+        let estimate = estimate.unwrap();
+        let (x, y) = estimate.position.get_cartesian();
+        assert!((x - 3.0).abs() < 0.3 && (y - 4.0).abs() < 0.3, "{:?}", estimate);
+        assert!(estimate.spread < 1.0, "{:?}", estimate);
+    }
+
+    #[test]
+    fn same_seed_is_reproducible() {
+        let signals = get_signals((6.0, 2.0));
+        let config = ParticleFilterConfig { particles: 200, resampling: Resampling::Stratified, seed: 7, ..ParticleFilterConfig::default() };
+
+        let mut first = ParticleFilter::new("A".into(), get_beacons(), config.clone());
+        let mut second = ParticleFilter::new("A".into(), get_beacons(), config);
+
+        for _ in 0..5 {
+            first.update(&signals);
+            second.update(&signals);
+        }
+
+        assert_eq!(first.get_particles(), second.get_particles());
+    }
+}
@@ -0,0 +1,159 @@
+// Cartesian points in the plane.  Radial is how positions are stored and reported,
+// but anything that adds, subtracts or measures between positions is simpler (and
+// safer around the axes) in x/y, so the radial math converts through here.
+
+use crate::identity::Identity;
+use crate::polar::Radial;
+
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Point2 {
+    pub x: f64,
+    pub y: f64,
+}
+
+// A displacement between two points.  Same math as a point, the name is just there to say which one a value means.
+pub type Vector2 = Point2;
+
+impl Point2 {
+    pub fn new(x: f64, y: f64) -> Point2 {
+        Point2 { x, y }
+    }
+
+    pub fn origin() -> Point2 {
+        Point2 { x: 0.0, y: 0.0 }
+    }
+
+    pub fn length(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+
+    pub fn distance(&self, other: &Point2) -> f64 {
+        (*other - *self).length()
+    }
+
+    pub fn dot(&self, other: &Point2) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    // The z component of the 3D cross product, positive when other is counterclockwise of self.
+    pub fn cross(&self, other: &Point2) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
+    // Angle from the positive x axis, normalized to [0, 2 PI).  A point sitting on the origin has an angle of 0.0.
+    pub fn angle(&self) -> f64 {
+        self.y.atan2(self.x).rem_euclid(PI * 2.0)
+    }
+
+    pub fn to_radial(self, id: Identity) -> Radial {
+        Radial {
+            id,
+            radius: self.length(),
+            angle: self.angle(),
+        }
+    }
+
+    pub fn from_radial(radial: &Radial) -> Point2 {
+        Point2 {
+            x: radial.angle.cos() * radial.radius,
+            y: radial.angle.sin() * radial.radius,
+        }
+    }
+}
+
+impl From<&Radial> for Point2 {
+    fn from(radial: &Radial) -> Point2 {
+        Point2::from_radial(radial)
+    }
+}
+
+impl From<(f64, f64)> for Point2 {
+    fn from((x, y): (f64, f64)) -> Point2 {
+        Point2 { x, y }
+    }
+}
+
+impl From<Point2> for (f64, f64) {
+    fn from(point: Point2) -> (f64, f64) {
+        (point.x, point.y)
+    }
+}
+
+impl Add for Point2 {
+    type Output = Point2;
+    fn add(self, other: Point2) -> Point2 {
+        Point2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Point2 {
+    fn add_assign(&mut self, other: Point2) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+}
+
+impl Sub for Point2 {
+    type Output = Point2;
+    fn sub(self, other: Point2) -> Point2 {
+        Point2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Point2 {
+    fn sub_assign(&mut self, other: Point2) {
+        self.x -= other.x;
+        self.y -= other.y;
+    }
+}
+
+impl Mul<f64> for Point2 {
+    type Output = Point2;
+    fn mul(self, scalar: f64) -> Point2 {
+        Point2::new(self.x * scalar, self.y * scalar)
+    }
+}
+
+impl Div<f64> for Point2 {
+    type Output = Point2;
+    fn div(self, scalar: f64) -> Point2 {
+        Point2::new(self.x / scalar, self.y / scalar)
+    }
+}
+
+impl Neg for Point2 {
+    type Output = Point2;
+    fn neg(self) -> Point2 {
+        Point2::new(-self.x, -self.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radial_round_trip_in_every_quadrant() {
+        for (x, y) in [(3.0, 4.0), (-3.0, 4.0), (-3.0, -4.0), (3.0, -4.0), (0.0, -2.0), (-2.0, 0.0)] {
+            let point = Point2::new(x, y);
+            let back = Point2::from(&point.to_radial("A".into()));
+
+            assert!(back.distance(&point) < 1e-12, "{:?} came back as {:?}", point, back);
+        }
+    }
+
+    #[test]
+    fn subtract_points_from_self_to_other_across_axes() {
+        let from = Radial { id: "A".into(), radius: 1.0, angle: PI * 0.75 };
+        let to = Radial { id: "B".into(), radius: 1.0, angle: PI * 1.75 };
+        let offset = from.subtract(&to);
+
+        assert!((offset.radius - 2.0).abs() < 1e-12);
+        assert!((offset.angle - PI * 1.75).abs() < 1e-12);
+        assert_eq!(from.subtract(&from).radius, 0.0);
+    }
+}
@@ -1,19 +1,16 @@
-use std::collections::{HashMap, hash_map::Entry};
-use std::ops::{Index, IndexMut};
-use std::f64::consts::{PI, FRAC_PI_2};
+use std::collections::HashMap;
+use std::ops::Index;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use crate::error::{Error, Result};
 use crate::identity::Identity;
+use crate::point::Point2;
 
 pub type Radius = f64;
 pub type Angle = f64;
 
-enum Quadrant {
-    TopRight,
-    TopLeft,
-    BottomLeft,
-    BottomRight,
-}
-
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PolarCoordinates {
     pub origin: Identity,
     pub radials: HashMap<Identity, Radial>,
@@ -26,30 +23,21 @@ impl Index<&'_ Identity> for PolarCoordinates {
     }
 }
 
-impl IndexMut<&'_ Identity> for PolarCoordinates {
-    fn index_mut(&mut self, k: &Identity) -> &mut Radial {
-        match self.radials.get_mut(k) {
-            Some(r) => r,
-            None => panic!("No radial found at that value.")
-        }
-    }
-}
-
 impl PolarCoordinates {
     pub fn new(origin: Identity) -> PolarCoordinates {
         PolarCoordinates {
-            origin: origin,
+            origin,
             radials: HashMap::<Identity, Radial>::new(),
         }
     }
 
-    pub fn from_distances(origin: Identity, calibration_id: Identity, skip_ids: Vec<Identity>, calibration: &Vec<(Identity, f64)>, distance_vec: &Vec<Vec<f64>>) -> PolarCoordinates {
+    pub fn from_distances(origin: Identity, calibration_id: Identity, skip_ids: Vec<Identity>, calibration: &[(Identity, f64)], distance_vec: &[Vec<f64>]) -> Result<PolarCoordinates> {
         let mut output = PolarCoordinates {
-            origin: origin,
+            origin,
             radials: HashMap::<Identity, Radial>::new(),
         };
 
-        let calibration_idx = calibration.iter().position(|x| x.0 == calibration_id).unwrap();
+        let calibration_idx = calibration.iter().position(|x| x.0 == calibration_id).ok_or(Error::UnknownIdentity(calibration_id))?;
 
         // let skip_ids: Vec<usize> = calibration.iter().map(|x| x.0).collect();
 
@@ -59,7 +47,8 @@ impl PolarCoordinates {
             angle: 0.0 as Angle
         });
 
-        let diff = distance_vec.len() - calibration.len();
+        // Every calibration entry needs a row of distances.
+        let diff = distance_vec.len().checked_sub(calibration.len()).ok_or(Error::InsufficientData { needed: calibration.len(), found: distance_vec.len() })?;
 
         for i in diff..distance_vec.len() {
             let idx = i - diff;
@@ -73,7 +62,7 @@ impl PolarCoordinates {
             output.add_b_edge(calibration[idx].0.clone(), a, b, c);
         }
 
-        return output;
+        Ok(output)
     }
 
     pub fn add_radial(&mut self, r: Radial) {
@@ -85,31 +74,40 @@ impl PolarCoordinates {
     }
 
     pub fn get(&self, key: &Identity) -> Option<&Radial> {
-        return self.radials.get(key);
+        self.radials.get(key)
     }
 
     pub fn get_mut(&mut self, key: &Identity) -> Option<&mut Radial> {
-        return self.radials.get_mut(key);
+        self.radials.get_mut(key)
+    }
+
+    // Same as get_mut, for callers passing errors up with ?.  This is what indexing mutably used to do, without the panic.
+    pub fn get_radial_mut(&mut self, key: &Identity) -> Result<&mut Radial> {
+        self.radials.get_mut(key).ok_or_else(|| Error::UnknownIdentity(key.clone()))
     }
 
-    // Takes the offset angle and compares the incoming angle based from the offset +/-.  The angle is kept if the offset + the incoming angle equal the given angle.  If not the kept angle is equal to the offset - the incoming angle.  This allows for negative angles, since distances will always calculate angles <180 degrees, but have no way to determine clockwise vs counterclockwise.
+    // Takes the offset angle and compares the incoming angle based from the offset +/-.  The kept angle is whichever of the offset + the incoming angle or the offset - the incoming angle is closest to the current angle, rather than an exact match, so noise doesn't flip it.  This allows for negative angles, since distances will always calculate angles <180 degrees, but have no way to determine clockwise vs counterclockwise.
 
-    // The positive and negative direction on the angle is relative to an "offset" angle direction and not an absolute clockwise/counterclockwise.
-    pub fn reconcile_radial(&mut self, offset_angle: Angle, id: Identity, radial: &Radial) {
-        let test_radial = self.get_mut(&id).unwrap();
+    // The positive and negative direction on the angle is relative to an "offset" angle direction and not an absolute clockwise/counterclockwise.  See mirror::resolve_mirror for picking the side of every radial against all of the distances.
+    pub fn reconcile_radial(&mut self, offset_angle: Angle, id: Identity, radial: &Radial) -> Result<()> {
+        let test_radial = self.get_radial_mut(&id)?;
 
         let pos_angle = offset_angle + radial.angle;
         let neg_angle = offset_angle - radial.angle;
 
-        if (test_radial.angle - pos_angle).abs() < 0.0000001 {
-            return;
+        let get_gap = |angle: Angle| (test_radial.angle - angle).rem_euclid(PI * 2.0).min((angle - test_radial.angle).rem_euclid(PI * 2.0));
+
+        if get_gap(pos_angle) <= get_gap(neg_angle) {
+            return Ok(());
         }
 
         test_radial.angle = neg_angle;
+        Ok(())
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Radial {
     pub id: Identity,
     pub radius: Radius,
@@ -119,7 +117,7 @@ pub struct Radial {
 impl Radial {
     pub fn empty(id: Identity) -> Radial {
         Radial {
-            id: id,
+            id,
             radius: 0.0,
             angle: 0.0
         }
@@ -127,61 +125,31 @@ impl Radial {
 
     pub fn from_distances(id: Identity, a: f64, b: f64, c:f64) -> Radial {
         Radial {
-            id: id,
+            id,
             radius: b,
             angle: get_unknown_triangle_angle(a, b, c)
         }
     }
     pub fn to_degrees(&self) -> Radial {
-        return Radial
+        Radial
         {
             id: self.id.clone(),
             radius: self.radius,
             angle: self.angle.to_degrees()
-        };
+        }
     }
 
     pub fn get_distance(&self, other: &Radial) -> f64 {
-        let (s_x, s_y) = self.get_cartesian();
-        let (o_x, o_y) = other.get_cartesian();
-
-        let x = o_x - s_x;
-        let y = o_y - s_y;
-
-        return (x.powi(2) + y.powi(2)).sqrt();
+        Point2::from(self).distance(&Point2::from(other))
     }
 
     pub fn get_cartesian(&self) -> (f64, f64) {
-        let x = (FRAC_PI_2 - self.angle).sin() * self.radius;
-        let y = self.angle.sin() * self.radius;
-
-        return (x, y);
+        Point2::from(self).into()
     }
 
+    // Returns the radial pointing from self to other.
     pub fn subtract(&self, other: &Radial) -> Radial {
-        let (s_x, s_y) = self.get_cartesian();
-        let (o_x, o_y) = other.get_cartesian();
-
-        let x = o_x - s_x;
-        let y = o_y - s_y;
-
-        let radius = (x.powi(2) + y.powi(2)).sqrt();
-        let angle = (y / radius).asin();
-
-        return Radial {
-            id: self.id.clone(),
-            radius: radius,
-            angle: match get_quadrant_from_cartesian(x, y) {
-                Quadrant::TopRight => angle,
-                Quadrant::TopLeft => {
-                    PI - angle
-                },
-                Quadrant::BottomLeft => {
-                    angle.abs() + PI
-                },
-                Quadrant::BottomRight => angle,
-            }
-        }
+        (Point2::from(other) - Point2::from(self)).to_radial(self.id.clone())
     }
 }
 
@@ -190,7 +158,8 @@ pub fn get_unknown_triangle_angle(a: f64, b: f64, c: f64) -> Angle {
         return 0.0;
     }
 
-    return ((a.powi(2) + b.powi(2) - c.powi(2)) / (2 as f64 * a * b)).acos();
+    // Noisy ranges can break the triangle inequality and push the cosine past +/-1, which would come back as NaN, so it is clamped to a flat triangle.
+    ((a.powi(2) + b.powi(2) - c.powi(2)) / (2_f64 * a * b)).clamp(-1.0, 1.0).acos()
 }
 
 pub fn get_unknown_triangle_side(a: f64, b: f64, theta: Angle) -> f64 {
@@ -206,58 +175,229 @@ pub fn get_unknown_triangle_side(a: f64, b: f64, theta: Angle) -> f64 {
     // a^2 + b^2 - c^2 / 2 * a * b = cos(theta)
     // sqrt(a^2 + b^2 - cos(theta) * 2 * a * b) = c
 
-    return (a.powi(2) + b.powi(2) - 2 as f64 * a * b * theta.cos()).sqrt();
+    (a.powi(2) + b.powi(2) - 2_f64 * a * b * theta.cos()).sqrt()
 }
 
-pub fn add_radials(radials: &Vec<Radial>) -> Radial {
-    let mut x = 0.0;
-    let mut y = 0.0;
+pub fn add_radials(radials: &[Radial]) -> Radial {
     let id = radials[0].id.clone();
-    // Law of Sines:
-    // a / sin(a) = c / sin(c)
-    // sin(pi/2) == 1
+    let mut sum = Point2::origin();
 
     for radial in radials {
-        x += (FRAC_PI_2 - radial.angle).sin() * radial.radius;
-        y += radial.angle.sin() * radial.radius;
-    }
-
-
-    let radius = (x.powi(2) + y.powi(2)).sqrt();
-    let angle = (y / radius).asin();
-
-    return Radial {
-        id: id,
-        radius: radius,
-        angle: match get_quadrant_from_cartesian(x, y) {
-            Quadrant::TopRight => angle,
-            Quadrant::TopLeft => {
-                PI - angle
-            },
-            Quadrant::BottomLeft => {
-                angle.abs() + PI
-            },
-            Quadrant::BottomRight => angle,
+        sum += Point2::from(radial);
+    }
+
+    sum.to_radial(id)
+}
+
+// Anything an agent can be positioned and moved with.  Radial covers the plane and SphericalRadial covers 3D, so 2D stays the default without any extra axis to carry around.
+pub trait Coordinate: Clone + Debug {
+    fn get_radius(&self) -> Radius;
+    fn with_radius(&self, radius: Radius) -> Self;
+    // The coordinate pointing from self to other.
+    fn get_offset(&self, other: &Self) -> Self;
+    fn add_all(coordinates: &[Self]) -> Self;
+    // Where the coordinate sits on the floor plan, looking straight down.
+    fn get_planar(&self) -> Point2;
+}
+
+impl Coordinate for Radial {
+    fn get_radius(&self) -> Radius {
+        self.radius
+    }
+
+    fn with_radius(&self, radius: Radius) -> Radial {
+        Radial { radius, ..self.clone() }
+    }
+
+    fn get_offset(&self, other: &Radial) -> Radial {
+        self.subtract(other)
+    }
+
+    fn add_all(coordinates: &[Radial]) -> Radial {
+        add_radials(coordinates)
+    }
+
+    fn get_planar(&self) -> Point2 {
+        Point2::from(self)
+    }
+}
+
+// A radial with an elevation off of the plane.  The azimuth is measured the same way as Radial::angle, and the elevation is the angle up (positive) or down (negative) from the plane, so a SphericalRadial with a 0.0 elevation is the same point as the Radial it came from.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SphericalRadial {
+    pub id: Identity,
+    pub radius: Radius,
+    pub azimuth: Angle,
+    pub elevation: Angle,
+}
+
+impl From<Radial> for SphericalRadial {
+    fn from(radial: Radial) -> SphericalRadial {
+        SphericalRadial {
+            id: radial.id,
+            radius: radial.radius,
+            azimuth: radial.angle,
+            elevation: 0.0,
+        }
+    }
+}
+
+impl SphericalRadial {
+    pub fn empty(id: Identity) -> SphericalRadial {
+        SphericalRadial {
+            id,
+            radius: 0.0,
+            azimuth: 0.0,
+            elevation: 0.0,
+        }
+    }
+
+    pub fn from_cartesian(id: Identity, x: f64, y: f64, z: f64) -> SphericalRadial {
+        let radius = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
+
+        if radius == 0.0 {
+            return SphericalRadial::empty(id);
+        }
+
+        SphericalRadial {
+            id,
+            radius,
+            azimuth: y.atan2(x).rem_euclid(PI * 2.0),
+            elevation: z.atan2(x.hypot(y)),
+        }
+    }
+
+    pub fn to_degrees(&self) -> SphericalRadial {
+        SphericalRadial {
+            id: self.id.clone(),
+            radius: self.radius,
+            azimuth: self.azimuth.to_degrees(),
+            elevation: self.elevation.to_degrees(),
+        }
+    }
+
+    // Drops the elevation, projecting the point straight down onto the plane.
+    pub fn to_radial(&self) -> Radial {
+        let (x, y, _) = self.get_cartesian();
+
+        Radial {
+            id: self.id.clone(),
+            radius: x.hypot(y),
+            angle: self.azimuth,
         }
-    };
+    }
+
+    pub fn get_cartesian(&self) -> (f64, f64, f64) {
+        let planar = self.elevation.cos() * self.radius;
+
+        (self.azimuth.cos() * planar, self.azimuth.sin() * planar, self.elevation.sin() * self.radius)
+    }
+
+    pub fn get_distance(&self, other: &SphericalRadial) -> f64 {
+        let (s_x, s_y, s_z) = self.get_cartesian();
+        let (o_x, o_y, o_z) = other.get_cartesian();
+
+        ((o_x - s_x).powi(2) + (o_y - s_y).powi(2) + (o_z - s_z).powi(2)).sqrt()
+    }
+
+    // Same direction as Radial::subtract, the result points from self to other.
+    pub fn subtract(&self, other: &SphericalRadial) -> SphericalRadial {
+        let (s_x, s_y, s_z) = self.get_cartesian();
+        let (o_x, o_y, o_z) = other.get_cartesian();
+
+        SphericalRadial::from_cartesian(self.id.clone(), o_x - s_x, o_y - s_y, o_z - s_z)
+    }
+}
+
+impl Coordinate for SphericalRadial {
+    fn get_radius(&self) -> Radius {
+        self.radius
+    }
+
+    fn with_radius(&self, radius: Radius) -> SphericalRadial {
+        SphericalRadial { radius, ..self.clone() }
+    }
+
+    fn get_offset(&self, other: &SphericalRadial) -> SphericalRadial {
+        self.subtract(other)
+    }
+
+    // Nothing to add up is no offset at all.
+    fn add_all(coordinates: &[SphericalRadial]) -> SphericalRadial {
+        add_spherical_radials(coordinates).unwrap_or_else(|| SphericalRadial::empty("".into()))
+    }
+
+    fn get_planar(&self) -> Point2 {
+        Point2::from(&self.to_radial())
+    }
+}
+
+// The sum takes the first radial's id, so there is nothing to return for an empty slice.
+pub fn add_spherical_radials(radials: &[SphericalRadial]) -> Option<SphericalRadial> {
+    let id = radials.first()?.id.clone();
+    let mut x = 0.0;
+    let mut y = 0.0;
+    let mut z = 0.0;
+
+    for radial in radials {
+        let (r_x, r_y, r_z) = radial.get_cartesian();
+        x += r_x;
+        y += r_y;
+        z += r_z;
+    }
+
+    Some(SphericalRadial::from_cartesian(id, x, y, z))
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SphericalCoordinates {
+    pub origin: Identity,
+    pub radials: HashMap<Identity, SphericalRadial>,
 }
 
-fn get_quadrant_from_cartesian(x: f64, y: f64) -> Quadrant {
-    if x >= 0.0 && y >= 0.0 {
-        return Quadrant::TopRight;
+impl Index<&'_ Identity> for SphericalCoordinates {
+    type Output = SphericalRadial;
+    fn index(&self, k: &Identity) -> &SphericalRadial {
+        &self.radials[k]
+    }
+}
+
+impl SphericalCoordinates {
+    pub fn new(origin: Identity) -> SphericalCoordinates {
+        SphericalCoordinates {
+            origin,
+            radials: HashMap::new(),
+        }
+    }
+
+    pub fn add_radial(&mut self, r: SphericalRadial) {
+        self.radials.insert(r.id.clone(), r);
     }
 
-    if x < 0.0 && y < 0.0 {
-        return Quadrant::BottomLeft;
+    pub fn get(&self, key: &Identity) -> Option<&SphericalRadial> {
+        self.radials.get(key)
     }
 
-    if x < 0.0 {
-        return Quadrant::TopLeft;
+    pub fn get_mut(&mut self, key: &Identity) -> Option<&mut SphericalRadial> {
+        self.radials.get_mut(key)
     }
 
-    if y < 0.0 {
-        return Quadrant::BottomRight;
+    // Flattens everything onto the 0 elevation plane.
+    pub fn to_polar(&self) -> PolarCoordinates {
+        PolarCoordinates {
+            origin: self.origin.clone(),
+            radials: self.radials.iter().map(|(id, r)| (id.clone(), r.to_radial())).collect(),
+        }
     }
+}
 
-    return Quadrant::TopRight;
-}
\ No newline at end of file
+impl From<PolarCoordinates> for SphericalCoordinates {
+    fn from(coordinates: PolarCoordinates) -> SphericalCoordinates {
+        SphericalCoordinates {
+            origin: coordinates.origin,
+            radials: coordinates.radials.into_iter().map(|(id, r)| (id, r.into())).collect(),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+// Aligns two independently solved frames.  Every solve picks its own origin and
+// 0 angle, so the same nodes come out rotated, shifted and possibly mirrored
+// between solves.  This finds the rigid transform (Procrustes / Umeyama without
+// scale, since ranges are already metric) that best maps one frame onto the other
+// using the identities they share.
+
+use crate::identity::Identity;
+use crate::point::{Point2, Vector2};
+use crate::polar::{Angle, PolarCoordinates, Radial};
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+// A rotation and translation are pinned down by two points.  Telling a mirror apart needs a third that isn't on the same line, which is checked by comparing the residuals.
+const MIN_SHARED_NODES: usize = 2;
+
+// How much better the mirrored fit has to be before it is preferred, so a perfectly collinear set (where both fit equally well) stays unmirrored.
+const REFLECTION_MARGIN: f64 = 1e-9;
+
+#[derive(Clone, Debug)]
+pub struct Registration {
+    // Counterclockwise rotation applied after the optional reflection.
+    pub rotation: Angle,
+    pub translation: Vector2,
+    // When true the source is mirrored across its x axis (y -> -y) before rotating.
+    pub reflected: bool,
+    // Root mean square distance between the transformed source nodes and the target nodes.
+    pub residual: f64,
+    pub shared: Vec<Identity>,
+}
+
+impl Registration {
+    pub fn apply(&self, point: Point2) -> Point2 {
+        let point = if self.reflected { Point2::new(point.x, -point.y) } else { point };
+        rotate(point, self.rotation) + self.translation
+    }
+
+    pub fn apply_radial(&self, radial: &Radial) -> Radial {
+        self.apply(Point2::from(radial)).to_radial(radial.id.clone())
+    }
+
+    // Moves every node of the source (its origin included) into the target's frame.  The target's origin is left out since it sits at the frame origin by definition.
+    pub fn transform(&self, source: &PolarCoordinates, target_origin: Identity) -> PolarCoordinates {
+        let mut output = PolarCoordinates::new(target_origin.clone());
+
+        for (id, point) in get_points(source) {
+            if id == target_origin {
+                continue;
+            }
+
+            output.add_radial(self.apply(point).to_radial(id));
+        }
+
+        output
+    }
+
+    // Angle the source's 0 axis drifted by to land on the target's.  Ignores the reflection, so only meaningful when comparing unmirrored frames.
+    pub fn get_drift(&self) -> Angle {
+        if self.rotation > PI {
+            self.rotation - PI * 2.0
+        } else {
+            self.rotation
+        }
+    }
+}
+
+fn rotate(point: Point2, angle: Angle) -> Point2 {
+    let (sin, cos) = angle.sin_cos();
+    Point2::new(cos * point.x - sin * point.y, sin * point.x + cos * point.y)
+}
+
+fn get_points(coordinates: &PolarCoordinates) -> HashMap<Identity, Point2> {
+    let mut points: HashMap<Identity, Point2> = coordinates
+        .radials
+        .iter()
+        .map(|(id, radial)| (id.clone(), Point2::from(radial)))
+        .collect();
+
+    points.insert(coordinates.origin.clone(), Point2::origin());
+    points
+}
+
+fn get_centroid(points: &[Point2]) -> Point2 {
+    let mut sum = Point2::origin();
+
+    for point in points {
+        sum += *point;
+    }
+
+    sum / points.len() as f64
+}
+
+// Closed form 2D Procrustes.  With both sets centered, the best rotation is the angle of the summed cross and dot products.
+fn fit(source: &[Point2], target: &[Point2], reflected: bool, shared: &[Identity]) -> Registration {
+    let source: Vec<Point2> = source
+        .iter()
+        .map(|p| if reflected { Point2::new(p.x, -p.y) } else { *p })
+        .collect();
+
+    let source_centroid = get_centroid(&source);
+    let target_centroid = get_centroid(target);
+
+    let mut dot = 0.0;
+    let mut cross = 0.0;
+
+    for (s, t) in source.iter().zip(target) {
+        let s = *s - source_centroid;
+        let t = *t - target_centroid;
+        dot += s.dot(&t);
+        cross += s.cross(&t);
+    }
+
+    let rotation = cross.atan2(dot).rem_euclid(PI * 2.0);
+    let translation = target_centroid - rotate(source_centroid, rotation);
+
+    let squared_error: f64 = source
+        .iter()
+        .zip(target)
+        .map(|(s, t)| (rotate(*s, rotation) + translation).distance(t).powi(2))
+        .sum();
+
+    Registration {
+        rotation,
+        translation,
+        reflected,
+        residual: (squared_error / source.len() as f64).sqrt(),
+        shared: shared.to_vec(),
+    }
+}
+
+// Finds the transform mapping source onto target.  Returns None when the frames share fewer than two nodes.
+pub fn register(source: &PolarCoordinates, target: &PolarCoordinates, allow_reflection: bool) -> Option<Registration> {
+    let source_points = get_points(source);
+    let target_points = get_points(target);
+
+    let mut shared: Vec<Identity> = source_points
+        .keys()
+        .filter(|id| target_points.contains_key(*id))
+        .cloned()
+        .collect();
+
+    if shared.len() < MIN_SHARED_NODES {
+        return None;
+    }
+
+    // Sorted so the result doesn't depend on HashMap ordering.
+    shared.sort();
+
+    let from: Vec<Point2> = shared.iter().map(|id| source_points[id]).collect();
+    let to: Vec<Point2> = shared.iter().map(|id| target_points[id]).collect();
+
+    let direct = fit(&from, &to, false, &shared);
+
+    if !allow_reflection {
+        return Some(direct);
+    }
+
+    let mirrored = fit(&from, &to, true, &shared);
+
+    if mirrored.residual < direct.residual - REFLECTION_MARGIN {
+        Some(mirrored)
+    } else {
+        Some(direct)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_frame(origin: &str, points: &[(&str, f64, f64)]) -> PolarCoordinates {
+        let mut coordinates = PolarCoordinates::new(origin.into());
+
+        for (id, x, y) in points {
+            coordinates.add_radial(Point2::new(*x, *y).to_radial((*id).into()));
+        }
+
+        coordinates
+    }
+
+    fn get_moved(points: &[(&'static str, f64, f64)], rotation: Angle, translation: Point2, reflected: bool) -> Vec<(&'static str, f64, f64)> {
+        let registration = Registration { rotation, translation, reflected, residual: 0.0, shared: vec![] };
+
+        points
+            .iter()
+            .map(|(id, x, y)| {
+                let moved = registration.apply(Point2::new(*x, *y));
+                (*id, moved.x, moved.y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recovers_rotation_and_translation() {
+        let points = [("A", 0.0, 0.0), ("B", 10.0, 0.0), ("C", 4.0, 7.0), ("D", -3.0, 5.0)];
+        let source = get_frame("A", &points[1..]);
+        let moved = get_moved(&points, 1.0, Point2::new(2.0, -3.0), false);
+        let target = get_frame("X", &moved);
+
+        let registration = register(&source, &target, true).unwrap();
+
+        assert!(!registration.reflected);
+        assert!((registration.rotation - 1.0).abs() < 1e-9, "{:?}", registration);
+        assert!(registration.translation.distance(&Point2::new(2.0, -3.0)) < 1e-9);
+        assert!(registration.residual < 1e-9);
+        assert_eq!(registration.shared.len(), 4);
+    }
+
+    #[test]
+    fn detects_reflection() {
+        let points = [("A", 0.0, 0.0), ("B", 10.0, 0.0), ("C", 4.0, 7.0), ("D", -3.0, 5.0)];
+        let source = get_frame("A", &points[1..]);
+        let target = get_frame("X", &get_moved(&points, 2.5, Point2::new(-1.0, 4.0), true));
+
+        let registration = register(&source, &target, true).unwrap();
+        assert!(registration.reflected);
+        assert!(registration.residual < 1e-9, "{:?}", registration);
+
+        let unmirrored = register(&source, &target, false).unwrap();
+        assert!(unmirrored.residual > 1.0);
+
+        let c = registration.transform(&source, "X".into())[&"C".into()].clone();
+        let expected = Point2::from(&target[&"C".into()]);
+        assert!(Point2::from(&c).distance(&expected) < 1e-9);
+    }
+}
@@ -1,4 +1,9 @@
-pub trait Signal {
+use crate::error::Result;
+
+// Signals have two encodings: a readable text form for logs and debugging, and a versioned binary frame (see wire.rs) for the radio.
+pub trait Signal: Sized {
     fn serialize(&self) -> String;
-    fn deserialize(data: String) -> Self;
-}
\ No newline at end of file
+    fn deserialize(data: String) -> Result<Self>;
+    fn encode(&self) -> Vec<u8>;
+    fn decode(data: &[u8]) -> Result<Self>;
+}
@@ -0,0 +1,197 @@
+// The 2D floor plan of a simulated site: walls, racking and anything else that gets
+// between two radios or in the way of an agent.  Obstacles are polygons with a
+// material, and a link that passes through one picks up the material's effect: UWB
+// takes a longer path around or through it (a positive range bias), signal strength
+// drops (attenuation), or nothing gets through at all (blocked).
+
+use crate::agent::Obstacles;
+use crate::point::Point2;
+
+// Anything closer to parallel than this is treated as not crossing.
+const PARALLEL_TOLERANCE: f64 = 1e-12;
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    // Added to a time of flight range for each obstacle of this material it passes through.
+    pub bias: f64,
+    // dB taken off the signal strength for each obstacle of this material it passes through.
+    pub attenuation: f64,
+    // Nothing gets through at all.
+    pub blocking: bool,
+}
+
+impl Material {
+    pub fn drywall() -> Material {
+        Material { bias: 0.1, attenuation: 3.0, blocking: false }
+    }
+
+    pub fn concrete() -> Material {
+        Material { bias: 0.5, attenuation: 12.0, blocking: false }
+    }
+
+    // Steel racking and shelving full of stock.
+    pub fn metal() -> Material {
+        Material { bias: 0.0, attenuation: 0.0, blocking: true }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Obstacle {
+    // In order around the outline, either way round.  The last vertex joins back to the first.
+    pub vertices: Vec<Point2>,
+    pub material: Material,
+}
+
+impl Obstacle {
+    pub fn new(vertices: Vec<Point2>, material: Material) -> Obstacle {
+        Obstacle { vertices, material }
+    }
+
+    pub fn rectangle(min: Point2, max: Point2, material: Material) -> Obstacle {
+        Obstacle::new(vec![min, Point2::new(max.x, min.y), max, Point2::new(min.x, max.y)], material)
+    }
+
+    fn get_edges(&self) -> impl Iterator<Item = (Point2, Point2)> + '_ {
+        let count = self.vertices.len();
+        (0..count).map(move |i| (self.vertices[i], self.vertices[(i + 1) % count]))
+    }
+
+    // Even-odd rule, casting a ray along +x.
+    pub fn contains(&self, point: Point2) -> bool {
+        let mut inside = false;
+
+        for (a, b) in self.get_edges() {
+            if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                inside = !inside;
+            }
+        }
+
+        inside
+    }
+
+    // How far along from to to (0.0 to 1.0) the segment first touches the outline, if it does.
+    pub fn get_intersection(&self, from: Point2, to: Point2) -> Option<f64> {
+        self.get_edges().filter_map(|(a, b)| get_segment_intersection(from, to, a, b)).min_by(|a, b| a.total_cmp(b))
+    }
+
+    pub fn is_crossed_by(&self, from: Point2, to: Point2) -> bool {
+        self.contains(from) || self.contains(to) || self.get_intersection(from, to).is_some()
+    }
+}
+
+// Fraction along p -> q where it meets a -> b, or None when they don't meet (or run parallel).
+fn get_segment_intersection(p: Point2, q: Point2, a: Point2, b: Point2) -> Option<f64> {
+    let direction = q - p;
+    let edge = b - a;
+    let denominator = direction.cross(&edge);
+
+    if denominator.abs() < PARALLEL_TOLERANCE {
+        return None;
+    }
+
+    let offset = a - p;
+    let t = offset.cross(&edge) / denominator;
+    let u = offset.cross(&direction) / denominator;
+
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+}
+
+// What the obstacles between two points do to a link between them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Link {
+    pub obstacles: usize,
+    pub bias: f64,
+    pub attenuation: f64,
+    pub blocked: bool,
+}
+
+impl Link {
+    pub fn is_line_of_sight(&self) -> bool {
+        self.obstacles == 0
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Environment {
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    pub fn add(&mut self, obstacle: Obstacle) {
+        self.obstacles.push(obstacle);
+    }
+
+    // Each obstacle the straight line between a and b passes through counts once, however many of its edges it crosses.
+    pub fn get_link(&self, a: Point2, b: Point2) -> Link {
+        let mut link = Link::default();
+
+        for obstacle in self.obstacles.iter().filter(|o| o.is_crossed_by(a, b)) {
+            link.obstacles += 1;
+            link.bias += obstacle.material.bias;
+            link.attenuation += obstacle.material.attenuation;
+            link.blocked |= obstacle.material.blocking;
+        }
+
+        link
+    }
+
+    pub fn is_inside(&self, point: Point2) -> bool {
+        self.obstacles.iter().any(|o| o.contains(point))
+    }
+
+    // How far along from to to (0.0 to 1.0) a move first hits an obstacle.  Obstacles from is already inside are ignored, so something that has ended up inside one can still get out.
+    pub fn get_collision(&self, from: Point2, to: Point2) -> Option<f64> {
+        self.obstacles
+            .iter()
+            .filter(|o| !o.contains(from))
+            .filter_map(|o| o.get_intersection(from, to))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+// So agents can be given the floor plan to collide with.
+impl Obstacles for Environment {
+    fn get_collision(&self, from: Point2, to: Point2) -> Option<f64> {
+        Environment::get_collision(self, from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_pick_up_what_they_pass_through() {
+        let mut environment = Environment::new();
+        environment.add(Obstacle::rectangle(Point2::new(4.0, -1.0), Point2::new(5.0, 1.0), Material::concrete()));
+        environment.add(Obstacle::rectangle(Point2::new(7.0, -1.0), Point2::new(7.2, 1.0), Material::drywall()));
+        environment.add(Obstacle::rectangle(Point2::new(0.0, 5.0), Point2::new(10.0, 6.0), Material::metal()));
+
+        let through = environment.get_link(Point2::new(0.0, 0.0), Point2::new(10.0, 0.0));
+        assert_eq!((through.obstacles, through.blocked), (2, false));
+        assert!((through.bias - 0.6).abs() < 1e-12 && (through.attenuation - 15.0).abs() < 1e-12);
+
+        assert!(environment.get_link(Point2::new(0.0, 2.0), Point2::new(10.0, 2.0)).is_line_of_sight());
+        assert!(environment.get_link(Point2::new(2.0, 0.0), Point2::new(2.0, 10.0)).blocked);
+    }
+
+    #[test]
+    fn collisions_stop_at_the_first_wall() {
+        let mut environment = Environment::new();
+        environment.add(Obstacle::new(vec![Point2::new(4.0, -1.0), Point2::new(6.0, -1.0), Point2::new(5.0, 1.0)], Material::metal()));
+
+        let hit = environment.get_collision(Point2::new(0.0, 0.0), Point2::new(10.0, 0.0)).unwrap();
+        assert!((hit - 0.45).abs() < 1e-12, "{}", hit);
+        assert_eq!(environment.get_collision(Point2::new(0.0, 2.0), Point2::new(10.0, 2.0)), None);
+
+        assert!(environment.is_inside(Point2::new(5.0, 0.0)));
+        assert_eq!(environment.get_collision(Point2::new(5.0, 0.0), Point2::new(5.0, -0.5)), None);
+    }
+}
@@ -0,0 +1,10 @@
+// Simulated worlds for exercising the solvers and filters against data that looks
+// like what the radios actually report, instead of perfect Euclidean distances.
+
+pub mod environment;
+pub mod ranging;
+pub mod scheduler;
+
+pub use environment::{Environment, Link, Material, Obstacle};
+pub use ranging::{NoiseModel, RangingConfig, RangingModel, RangingSimulator};
+pub use scheduler::{Scheduler, SwarmConfig, SwarmEvent, SwarmSimulator};
@@ -0,0 +1,288 @@
+// Turns ground truth positions into the ranges a radio would report.  A ranging
+// model covers how the distance is measured (round trip time, or received signal
+// strength run back through a path loss curve) along with that method's own errors,
+// then extra noise, dropped measurements and a maximum range go on top.  Everything
+// is drawn from a seeded RNG so a run can be repeated exactly.  Give the simulator
+// an Environment and links through obstacles pick up their bias and attenuation,
+// or aren't measured at all when blocked.
+
+use crate::identity::Identity;
+use crate::location::{DistanceGraph, MomentEdge};
+use crate::particle::get_gaussian;
+use crate::simulation::environment::{Environment, Link};
+use crate::point::Point2;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// In metres per second.
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RangingModel {
+    // The true distance, plus any bias from obstacles.
+    Ideal,
+    // Single sided two way ranging.  The initiator times the round trip and the responder reports how long it held the message, and each of the four timestamps has its own jitter.  Each responder's clock runs off from its initiator's by a fixed amount of up to drift_ppm, drawn once per pair, which scales its reply delay and so biases every range between them by the same amount.
+    TimeOfFlight {
+        // Standard deviation of each timestamp, in seconds.
+        clock_jitter: f64,
+        drift_ppm: f64,
+        // Seconds the responder holds the message before replying.
+        reply_delay: f64,
+    },
+    // Log-distance path loss.  The RSSI falls off by 10 * exponent dB per decade of distance from reference_rssi at 1 m, plus log-normal shadowing, and the range is what that RSSI works back out to.  Anything weaker than sensitivity isn't heard at all.
+    PathLoss {
+        // In dBm.
+        reference_rssi: f64,
+        exponent: f64,
+        // Standard deviation of the shadowing, in dB.
+        shadowing: f64,
+        // In dBm.
+        sensitivity: f64,
+    },
+}
+
+impl RangingModel {
+    // Typical UWB numbers: ~15 ps timestamps, crystals within 20 ppm and a 300 us reply.
+    pub fn time_of_flight() -> RangingModel {
+        RangingModel::TimeOfFlight { clock_jitter: 15e-12, drift_ppm: 20.0, reply_delay: 300e-6 }
+    }
+
+    // Typical indoor BLE numbers.
+    pub fn path_loss() -> RangingModel {
+        RangingModel::PathLoss { reference_rssi: -59.0, exponent: 2.0, shadowing: 4.0, sensitivity: -95.0 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NoiseModel {
+    None,
+    // Zero mean, with the given standard deviation.
+    Gaussian(f64),
+    // Zero mean, with the given scale.  Heavier tails than a Gaussian, so more of the occasional wild reading.
+    Laplace(f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct RangingConfig {
+    pub model: RangingModel,
+    // Added to whatever the model measured.
+    pub noise: NoiseModel,
+    // Chance that any one measurement is lost.
+    pub dropout: f64,
+    // Pairs further apart than this never get a measurement.
+    pub max_range: f64,
+    pub seed: u64,
+}
+
+impl Default for RangingConfig {
+    fn default() -> Self {
+        RangingConfig {
+            model: RangingModel::time_of_flight(),
+            noise: NoiseModel::None,
+            dropout: 0.0,
+            max_range: 50.0,
+            seed: 0,
+        }
+    }
+}
+
+pub struct RangingSimulator {
+    pub config: RangingConfig,
+    // Shared, so a simulation can hand the same floor plan to its agents to collide with.
+    pub environment: Arc<Environment>,
+    rng: StdRng,
+    // Clock drift of each responder against its initiator, keyed initiator first.  Drawn the first time the pair ranges.
+    drifts: HashMap<(Identity, Identity), f64>,
+}
+
+impl RangingSimulator {
+    pub fn new(config: RangingConfig) -> RangingSimulator {
+        RangingSimulator::with_environment(config, Arc::new(Environment::new()))
+    }
+
+    pub fn with_environment(config: RangingConfig, environment: Arc<Environment>) -> RangingSimulator {
+        let rng = StdRng::seed_from_u64(config.seed);
+
+        RangingSimulator { config, environment, rng, drifts: HashMap::new() }
+    }
+
+    // Gives the rng to callers that need to draw alongside the measurements, so one seed still covers the whole run.
+    pub fn get_rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    // What the radio reports for a true distance in open space, or None when the measurement is lost.  Every call is treated as the same pair of radios.  Ranges never come out negative.
+    pub fn measure(&mut self, distance: f64) -> Option<f64> {
+        let anonymous: Identity = "".into();
+        self.measure_link(distance, &Link::default(), (&anonymous, &anonymous))
+    }
+
+    // Same as measure, with a initiating and b responding, through whatever the environment puts between them.
+    pub fn measure_between(&mut self, a: &(Identity, Point2), b: &(Identity, Point2)) -> Option<f64> {
+        let link = self.environment.get_link(a.1, b.1);
+        self.measure_link(a.1.distance(&b.1), &link, (&a.0, &b.0))
+    }
+
+    fn get_drift(&mut self, (initiator, responder): (&Identity, &Identity), drift_ppm: f64) -> f64 {
+        let rng = &mut self.rng;
+        *self.drifts.entry((initiator.clone(), responder.clone())).or_insert_with(|| rng.gen_range(-1.0..=1.0) * drift_ppm * 1e-6)
+    }
+
+    fn measure_link(&mut self, distance: f64, link: &Link, pair: (&Identity, &Identity)) -> Option<f64> {
+        if link.blocked || distance > self.config.max_range || self.rng.gen::<f64>() < self.config.dropout {
+            return None;
+        }
+
+        // Ranges timed off the first path to arrive read long when that path went round or through an obstacle.
+        let measured = match self.config.model {
+            RangingModel::Ideal => distance + link.bias,
+            RangingModel::TimeOfFlight { clock_jitter, drift_ppm, reply_delay } => {
+                let tof = distance / SPEED_OF_LIGHT;
+                let drift = self.get_drift(pair, drift_ppm);
+                let mut jitter = || get_gaussian(&mut self.rng) * clock_jitter;
+
+                // t1 poll sent, t2 poll received, t3 response sent, t4 response received.  The responder measures its reply delay on its own clock.
+                let (t1, t4) = (jitter(), 2.0 * tof + reply_delay + jitter());
+                let (t2, t3) = (jitter(), reply_delay * (1.0 + drift) + jitter());
+
+                SPEED_OF_LIGHT * ((t4 - t1) - (t3 - t2)) / 2.0 + link.bias
+            },
+            RangingModel::PathLoss { reference_rssi, exponent, shadowing, sensitivity } => {
+                // Closer than 10 cm the far field model stops meaning anything.
+                let rssi = reference_rssi - 10.0 * exponent * distance.max(0.1).log10() + get_gaussian(&mut self.rng) * shadowing - link.attenuation;
+
+                if rssi < sensitivity {
+                    return None;
+                }
+
+                10f64.powf((reference_rssi - rssi) / (10.0 * exponent))
+            },
+        };
+
+        let noise = match self.config.noise {
+            NoiseModel::None => 0.0,
+            NoiseModel::Gaussian(std) => get_gaussian(&mut self.rng) * std,
+            NoiseModel::Laplace(scale) => {
+                let u: f64 = self.rng.gen_range(-0.5..0.5);
+                -scale * u.signum() * (1.0 - 2.0 * u.abs()).max(f64::MIN_POSITIVE).ln()
+            },
+        };
+
+        Some((measured + noise).max(0.0))
+    }
+
+    // samples measurements of every pair of positions, left to right in the order given.
+    pub fn get_edges(&mut self, positions: &[(Identity, Point2)], samples: usize, timestamp: SystemTime) -> Vec<MomentEdge> {
+        let mut edges = vec![];
+
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                for _ in 0..samples {
+                    if let Some(measured) = self.measure_between(a, b) {
+                        edges.push(MomentEdge::new(a.0.clone(), b.0.clone(), measured, timestamp));
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+
+    pub fn get_distance_graph(&mut self, positions: &[(Identity, Point2)], samples: usize, timestamp: SystemTime) -> DistanceGraph {
+        DistanceGraph::from_edges(self.get_edges(positions, samples, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_stats(simulator: &mut RangingSimulator, distance: f64, samples: usize) -> (usize, f64, f64) {
+        let measured: Vec<f64> = (0..samples).filter_map(|_| simulator.measure(distance)).collect();
+        let mean = measured.iter().sum::<f64>() / measured.len() as f64;
+        let std = (measured.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / measured.len() as f64).sqrt();
+
+        (measured.len(), mean, std)
+    }
+
+    #[test]
+    fn models_have_the_expected_error() {
+        // With no drift, two way ranging is unbiased and each of the four timestamps adds jitter / 2 of spread, so the range spread is c * jitter.
+        let tof = RangingModel::TimeOfFlight { clock_jitter: 1e-10, drift_ppm: 0.0, reply_delay: 300e-6 };
+        let mut simulator = RangingSimulator::new(RangingConfig { model: tof, ..RangingConfig::default() });
+        let (_, mean, std) = get_stats(&mut simulator, 10.0, 5_000);
+        assert!((mean - 10.0).abs() < 0.01, "{}", mean);
+        assert!((std - SPEED_OF_LIGHT * 1e-10).abs() < 0.005, "{}", std);
+
+        // Path loss without shadowing inverts exactly, and falls silent past the sensitivity.
+        let loss = RangingModel::PathLoss { reference_rssi: -59.0, exponent: 2.0, shadowing: 0.0, sensitivity: -79.0 };
+        let mut simulator = RangingSimulator::new(RangingConfig { model: loss, max_range: 100.0, ..RangingConfig::default() });
+        assert!((simulator.measure(7.0).unwrap() - 7.0).abs() < 1e-9);
+        assert_eq!(simulator.measure(11.0), None);
+
+        let laplace = RangingConfig { model: RangingModel::Ideal, noise: NoiseModel::Laplace(0.5), ..RangingConfig::default() };
+        let (_, mean, std) = get_stats(&mut RangingSimulator::new(laplace), 10.0, 20_000);
+        assert!((mean - 10.0).abs() < 0.02 && (std - 0.5 * 2f64.sqrt()).abs() < 0.03, "{} {}", mean, std);
+    }
+
+    #[test]
+    fn obstacles_bias_attenuate_and_block() {
+        use crate::simulation::environment::{Material, Obstacle};
+
+        let mut environment = Environment::new();
+        environment.add(Obstacle::rectangle(Point2::new(4.0, -1.0), Point2::new(5.0, 1.0), Material::concrete()));
+        environment.add(Obstacle::rectangle(Point2::new(4.0, 4.0), Point2::new(5.0, 6.0), Material::metal()));
+        let environment = Arc::new(environment);
+        let (a, b) = (("A".into(), Point2::new(0.0, 0.0)), ("B".into(), Point2::new(10.0, 0.0)));
+
+        let mut tof = RangingSimulator::with_environment(RangingConfig { model: RangingModel::Ideal, ..RangingConfig::default() }, environment.clone());
+        assert!((tof.measure_between(&a, &b).unwrap() - 10.5).abs() < 1e-12);
+        assert_eq!(tof.measure_between(&("A".into(), Point2::new(0.0, 5.0)), &("B".into(), Point2::new(10.0, 5.0))), None);
+
+        // 12 dB of wall with a path loss exponent of 2 reads as 10^0.6 times further away.
+        let loss = RangingModel::PathLoss { reference_rssi: -59.0, exponent: 2.0, shadowing: 0.0, sensitivity: -100.0 };
+        let mut rssi = RangingSimulator::with_environment(RangingConfig { model: loss, ..RangingConfig::default() }, environment);
+        assert!((rssi.measure_between(&a, &b).unwrap() - 10.0 * 10f64.powf(0.6)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn drift_biases_each_pair_consistently() {
+        let tof = RangingModel::TimeOfFlight { clock_jitter: 0.0, drift_ppm: 20.0, reply_delay: 300e-6 };
+        let mut simulator = RangingSimulator::new(RangingConfig { model: tof, ..RangingConfig::default() });
+        let positions: Vec<(Identity, Point2)> = vec![("A".into(), Point2::new(0.0, 0.0)), ("B".into(), Point2::new(10.0, 0.0)), ("C".into(), Point2::new(0.0, 10.0))];
+
+        // With no jitter, every range between a pair is off by exactly its drift.
+        let edges = simulator.get_edges(&positions, 50, SystemTime::now());
+        let truth = |id: &Identity| positions.iter().find(|p| &p.0 == id).unwrap().1;
+        let mut biases: Vec<f64> = vec![];
+
+        for pair in edges.chunks(50) {
+            let bias = pair[0].distance - truth(&pair[0].left).distance(&truth(&pair[0].right));
+            assert!(pair.iter().all(|e| e.distance == pair[0].distance), "{:?}", pair);
+            // 20 ppm of a 300 us reply is up to 6 ns of round trip, or about 0.9 m.
+            assert!(bias.abs() < 0.9 && bias.abs() > 1e-6, "{}", bias);
+            biases.push(bias);
+        }
+
+        assert_eq!(biases.len(), 3);
+        assert!(biases[0] != biases[1] && biases[1] != biases[2]);
+    }
+
+    #[test]
+    fn dropout_range_and_seed() {
+        let config = RangingConfig { model: RangingModel::Ideal, noise: NoiseModel::Gaussian(0.3), dropout: 0.25, max_range: 20.0, seed: 7 };
+        let positions: Vec<(Identity, Point2)> = vec![("A".into(), Point2::new(0.0, 0.0)), ("B".into(), Point2::new(10.0, 0.0)), ("C".into(), Point2::new(40.0, 0.0))];
+        let now = SystemTime::now();
+
+        let edges = RangingSimulator::new(config.clone()).get_edges(&positions, 1_000, now);
+        // Only A-B is in range, and about a quarter of its samples are lost.
+        assert!(edges.iter().all(|e| e.left.as_ref() == "A" && e.right.as_ref() == "B"));
+        assert!((700..800).contains(&edges.len()), "{}", edges.len());
+
+        let again = RangingSimulator::new(config).get_edges(&positions, 1_000, now);
+        assert_eq!(edges.iter().map(|e| e.distance).collect::<Vec<f64>>(), again.iter().map(|e| e.distance).collect::<Vec<f64>>());
+    }
+}
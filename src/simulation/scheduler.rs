@@ -0,0 +1,363 @@
+// Discrete event simulation.  Instead of sleeping and polling, everything that
+// should happen (an agent step, a beacon poll, a round of ranging) is an event at a
+// simulated time, and the scheduler jumps the clock straight from one event to the
+// next.  Thousands of simulated seconds run in however long the events themselves
+// take, and with the same seed a run comes out the same every time.
+
+use crate::agent_manager::AgentManager;
+use crate::beacon::{Beacon, BeaconSignal};
+use crate::clock::{Clock, SimulatedClock};
+use crate::identity::Identity;
+use crate::location::{DistanceGraph, MomentEdge};
+use crate::polar::Coordinate;
+use crate::simulation::environment::Environment;
+use crate::simulation::ranging::{RangingConfig, RangingSimulator};
+
+use rand::rngs::StdRng;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// Shortest interval any of the swarm's events repeat at.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Scheduled<E> {
+    at: SystemTime,
+    // Breaks ties between events at the same time in the order they were scheduled.
+    sequence: u64,
+    event: E,
+}
+
+impl<E> PartialEq for Scheduled<E> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl<E> Eq for Scheduled<E> {}
+
+impl<E> PartialOrd for Scheduled<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Scheduled<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+pub struct Scheduler<E> {
+    pub clock: SimulatedClock,
+    queue: BinaryHeap<Reverse<Scheduled<E>>>,
+    sequence: u64,
+}
+
+impl<E> Scheduler<E> {
+    pub fn new(clock: SimulatedClock) -> Scheduler<E> {
+        Scheduler {
+            clock,
+            queue: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    // Events in the past run next, at the current time.
+    pub fn schedule_at(&mut self, at: SystemTime, event: E) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Scheduled { at: at.max(self.clock.now()), sequence: self.sequence, event }));
+    }
+
+    pub fn schedule_in(&mut self, delay: Duration, event: E) {
+        self.schedule_at(self.clock.now() + delay, event);
+    }
+
+    pub fn get_next_time(&self) -> Option<SystemTime> {
+        self.queue.peek().map(|Reverse(scheduled)| scheduled.at)
+    }
+
+    // Takes the next event and moves the clock up to it.
+    pub fn next_event(&mut self) -> Option<E> {
+        let Reverse(scheduled) = self.queue.pop()?;
+        self.clock.set(scheduled.at);
+
+        Some(scheduled.event)
+    }
+
+    // Runs every event up to and including end through handle, which can schedule more, then leaves the clock at end.
+    pub fn run_until(&mut self, end: SystemTime, mut handle: impl FnMut(&mut Scheduler<E>, E)) {
+        while self.get_next_time().is_some_and(|at| at <= end) {
+            if let Some(event) = self.next_event() {
+                handle(self, event);
+            }
+        }
+
+        self.clock.set(end);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwarmEvent {
+    // Every agent follows its path up to now.
+    MoveAgents,
+    // Every agent ranges to every beacon.
+    Measure,
+    // Every beacon reports the newest range it holds to each agent.
+    PollBeacons,
+}
+
+#[derive(Clone, Debug)]
+pub struct SwarmConfig {
+    pub agent_step: Duration,
+    pub measure_interval: Duration,
+    pub beacon_poll: Duration,
+    pub ranging: RangingConfig,
+    // Seeds the ranging, and anything else drawn from get_rng.  Overrides the ranging config's own seed.
+    pub seed: u64,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        SwarmConfig {
+            agent_step: Duration::from_millis(100),
+            measure_interval: Duration::from_millis(200),
+            beacon_poll: Duration::from_millis(1_000),
+            ranging: RangingConfig::default(),
+            seed: 0,
+        }
+    }
+}
+
+// Agents moving among fixed beacons, ranged on a schedule in simulated time.  Beacons are driven directly rather than through their worker threads, so nothing depends on how the OS schedules threads.
+pub struct SwarmSimulator {
+    pub config: SwarmConfig,
+    pub agents: AgentManager,
+    pub beacons: Vec<Beacon>,
+    pub ranging: RangingSimulator,
+    // What the beacons reported at each poll, beacon on the left and agent on the right.
+    pub reports: DistanceGraph,
+    scheduler: Scheduler<SwarmEvent>,
+}
+
+impl SwarmSimulator {
+    pub fn new(config: SwarmConfig, start: SystemTime) -> SwarmSimulator {
+        SwarmSimulator::with_environment(config, start, Environment::new())
+    }
+
+    // The same floor plan biases and blocks the ranging and stops the agents, including agents added later through agents.add_agent.
+    pub fn with_environment(mut config: SwarmConfig, start: SystemTime, environment: Environment) -> SwarmSimulator {
+        // An event that repeats every zero seconds would run forever without the clock moving.
+        for interval in [&mut config.agent_step, &mut config.measure_interval, &mut config.beacon_poll] {
+            *interval = (*interval).max(MIN_INTERVAL);
+        }
+
+        let clock = SimulatedClock::new(start);
+        let environment = Arc::new(environment);
+        let ranging = RangingSimulator::with_environment(RangingConfig { seed: config.seed, ..config.ranging.clone() }, environment.clone());
+        let mut agents = AgentManager::with_clock(clock.shared());
+        agents.set_obstacles(environment);
+
+        let mut scheduler = Scheduler::new(clock.clone());
+        scheduler.schedule_in(Duration::ZERO, SwarmEvent::MoveAgents);
+        scheduler.schedule_in(Duration::ZERO, SwarmEvent::Measure);
+        scheduler.schedule_in(config.beacon_poll, SwarmEvent::PollBeacons);
+
+        SwarmSimulator {
+            config,
+            agents,
+            beacons: vec![],
+            ranging,
+            reports: DistanceGraph::new(),
+            scheduler,
+        }
+    }
+
+    pub fn get_clock(&self) -> SimulatedClock {
+        self.scheduler.clock.clone()
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.scheduler.clock.now()
+    }
+
+    pub fn get_rng(&mut self) -> &mut StdRng {
+        self.ranging.get_rng()
+    }
+
+    // Beacons added here age their readings on the simulated clock.
+    pub fn add_beacon(&mut self, mut beacon: Beacon) {
+        beacon.set_clock(self.get_clock().shared());
+        self.beacons.push(beacon);
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.now() + duration;
+        // The handler needs self, so the scheduler is taken out for the run and put back after.
+        let clock = self.get_clock();
+        let mut scheduler = std::mem::replace(&mut self.scheduler, Scheduler::new(clock));
+
+        scheduler.run_until(end, |scheduler, event| {
+            let next = match event {
+                SwarmEvent::MoveAgents => {
+                    self.move_agents();
+                    self.config.agent_step
+                },
+                SwarmEvent::Measure => {
+                    self.measure();
+                    self.config.measure_interval
+                },
+                SwarmEvent::PollBeacons => {
+                    self.poll_beacons();
+                    self.config.beacon_poll
+                },
+            };
+
+            // config is public, so it could have been zeroed since new.
+            scheduler.schedule_in(next.max(MIN_INTERVAL), event);
+        });
+
+        self.scheduler = scheduler;
+    }
+
+    fn move_agents(&mut self) {
+        for id in self.agents.get_agent_ids() {
+            self.agents.get_agent_position(&id);
+        }
+    }
+
+    fn measure(&mut self) {
+        let now = self.now();
+
+        for id in self.agents.get_agent_ids() {
+            let agent = match self.agents.get_agent(&id) {
+                Some(agent) => (id.clone(), agent.position.get_planar()),
+                None => continue,
+            };
+
+            for beacon in &self.beacons {
+                if let Some(distance) = self.ranging.measure_between(&agent, &(beacon.id.clone(), beacon.position.get_planar())) {
+                    beacon.inject(BeaconSignal { id: id.clone(), distance, timestamp: now });
+                }
+            }
+        }
+    }
+
+    fn poll_beacons(&mut self) {
+        let agents: Vec<Identity> = self.agents.get_agent_ids();
+
+        for beacon in &mut self.beacons {
+            for edge in beacon.get_distances() {
+                if agents.contains(&edge.right) {
+                    self.reports.add(MomentEdge { left: beacon.id.clone(), ..edge });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Agent;
+    use crate::point::Point2;
+    use crate::simulation::ranging::{NoiseModel, RangingModel};
+
+    fn get_run(seed: u64) -> SwarmSimulator {
+        let config = SwarmConfig { seed, ranging: RangingConfig { noise: NoiseModel::Gaussian(0.1), dropout: 0.1, ..RangingConfig::default() }, ..SwarmConfig::default() };
+        let mut simulator = SwarmSimulator::new(config, SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+
+        for (id, x, y) in [("B1", 0.0, 0.0), ("B2", 20.0, 0.0), ("B3", 0.0, 20.0)] {
+            simulator.add_beacon(Beacon::new(id.into(), Point2::new(x, y).to_radial(id.into())));
+        }
+
+        // 1 m/s, so 10 m out to the first waypoint and 10 m back.
+        let mut agent = Agent::new("R1".into(), 0, Point2::new(5.0, 5.0).to_radial("R1".into()), 0.001);
+        agent.send_position(&Point2::new(15.0, 5.0).to_radial("R1".into()));
+        agent.send_position(&Point2::new(5.0, 5.0).to_radial("R1".into()));
+        simulator.agents.add_agent(agent);
+
+        simulator.run_for(Duration::from_secs(3_600));
+        simulator
+    }
+
+    #[test]
+    fn an_hour_runs_instantly_and_repeats() {
+        let started = std::time::Instant::now();
+        let first = get_run(3);
+        // Nowhere near the hour it simulates, even in a debug build.
+        assert!(started.elapsed() < Duration::from_secs(30));
+
+        assert_eq!(first.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000 + 3_600));
+        let position = first.agents.get_agent(&"R1".into()).unwrap().position.get_planar();
+        assert!(position.distance(&Point2::new(5.0, 5.0)) < 1e-6, "{:?}", position);
+
+        // One report from each beacon every second.
+        assert_eq!(first.reports.distances.len(), 3 * 3_600);
+        assert_eq!(first.reports.distances, get_run(3).reports.distances);
+        assert_ne!(first.reports.distances, get_run(4).reports.distances);
+    }
+
+    #[test]
+    fn walls_stop_agents_and_block_ranging() {
+        use crate::simulation::environment::{Material, Obstacle};
+
+        let mut environment = Environment::new();
+        environment.add(Obstacle::rectangle(Point2::new(9.0, -5.0), Point2::new(10.0, 15.0), Material::metal()));
+        let config = SwarmConfig { ranging: RangingConfig { model: RangingModel::Ideal, ..RangingConfig::default() }, ..SwarmConfig::default() };
+        let mut simulator = SwarmSimulator::with_environment(config, SystemTime::UNIX_EPOCH, environment);
+
+        simulator.add_beacon(Beacon::new("B1".into(), Point2::new(0.0, 0.0).to_radial("B1".into())));
+        simulator.add_beacon(Beacon::new("B2".into(), Point2::new(20.0, 5.0).to_radial("B2".into())));
+
+        let mut agent = Agent::new("R1".into(), 0, Point2::new(5.0, 5.0).to_radial("R1".into()), 0.001);
+        agent.send_position(&Point2::new(15.0, 5.0).to_radial("R1".into()));
+        simulator.agents.add_agent(agent);
+        simulator.run_for(Duration::from_secs(30));
+
+        let agent = simulator.agents.get_agent(&"R1".into()).unwrap();
+        assert!(agent.collided && agent.position.get_planar().x < 9.0, "{:?}", agent.position.get_planar());
+
+        // B2 is on the far side of the wall the whole time.
+        assert!(!simulator.reports.lefts.is_empty());
+        assert!(simulator.reports.lefts.iter().all(|id| id.as_ref() == "B1"));
+    }
+
+    #[test]
+    fn zero_intervals_are_clamped() {
+        let config = SwarmConfig { agent_step: Duration::ZERO, measure_interval: Duration::ZERO, beacon_poll: Duration::ZERO, ..SwarmConfig::default() };
+        let mut simulator = SwarmSimulator::new(config, SystemTime::UNIX_EPOCH);
+        simulator.run_for(Duration::from_millis(10));
+
+        assert_eq!(simulator.config.agent_step, MIN_INTERVAL);
+        assert_eq!(simulator.now(), SystemTime::UNIX_EPOCH + Duration::from_millis(10));
+    }
+
+    #[test]
+    fn scheduler_runs_events_in_time_order() {
+        let clock = SimulatedClock::new(SystemTime::UNIX_EPOCH);
+        let mut scheduler = Scheduler::new(clock.clone());
+        scheduler.schedule_in(Duration::from_secs(2), "b");
+        scheduler.schedule_in(Duration::from_secs(1), "a");
+        scheduler.schedule_in(Duration::from_secs(2), "c");
+        scheduler.schedule_in(Duration::from_secs(9), "late");
+
+        let mut seen = vec![];
+        scheduler.run_until(SystemTime::UNIX_EPOCH + Duration::from_secs(5), |scheduler, event| {
+            seen.push((event, scheduler.clock.now()));
+        });
+
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(seen, vec![("a", at(1)), ("b", at(2)), ("c", at(2))]);
+        assert_eq!((clock.now(), scheduler.len()), (at(5), 1));
+    }
+}
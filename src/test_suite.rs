@@ -1,23 +1,22 @@
-use std::cmp;
 use std::collections::HashMap;
 use std::time::SystemTime;
-use datetime::LocalDateTime;
-use rand::{seq::SliceRandom, thread_rng};
-use colored::Colorize;
+use rand::seq::SliceRandom;
 
-use crate::identity::Identity;
-use crate::location::{DistanceGraph, MomentEdge};
-use crate::polar::PolarCoordinates;
-use crate::filter::beam_deviation_filter;
+use navigation::identity::Identity;
+use navigation::location::DistanceGraph;
+use navigation::point::Point2;
+use navigation::polar::PolarCoordinates;
+use navigation::filter::BeamDeviationFilter;
+use navigation::simulation::ranging::{RangingConfig, RangingModel, RangingSimulator};
 
 use rand::prelude::*;
 
 pub fn page_break() {
-    println!("{}", format!("\n{:#<80}\n", ""));
+    println!("\n{:#<80}\n", "");
 }
 
 pub fn line_break() {
-    println!("{}", format!("\n{:-<80}\n", ""));
+    println!("\n{:-<80}\n", "");
 }
 
 fn get_node_alpha_table() -> Vec<Identity> {
@@ -77,22 +76,15 @@ fn get_node_names(nodes: usize) -> Vec<Identity> {
         }
     }
 
-    return output;
+    output
 }
 
+// Exact ranges between every pair of grid nodes, from the same simulator the noisier scenarios use.
 pub fn get_distance_graph(nodes: Vec<(Identity, usize, usize)>) -> DistanceGraph {
-    let mut edges: Vec<MomentEdge> = vec![];
-
-    for i in 0..nodes.len() {
-        for j in (i + 1)..nodes.len() {
-            let x = (nodes[i].1 as f64 - nodes[j].1 as f64) as f64;
-            let y = (nodes[i].2 as f64 - nodes[j].2 as f64) as f64;
-            let dist = ((x * x + y * y) as f64).sqrt();
-            edges.push(MomentEdge { left: nodes[i].0.clone(), right: nodes[j].0.clone(), distance: dist, timestamp: SystemTime::now() });
-        }
-    }
+    let positions: Vec<(Identity, Point2)> = nodes.into_iter().map(|(id, x, y)| (id, Point2::new(x as f64, y as f64))).collect();
+    let config = RangingConfig { model: RangingModel::Ideal, max_range: f64::INFINITY, ..RangingConfig::default() };
 
-    return DistanceGraph::from_edges(edges);
+    RangingSimulator::new(config).get_distance_graph(&positions, 1, SystemTime::now())
 }
 
 pub fn create_nodes_with_positions(nodes: usize, grid: (usize, usize)) -> Vec<(Identity, usize, usize)> {
@@ -108,7 +100,7 @@ pub fn create_nodes_with_positions(nodes: usize, grid: (usize, usize)) -> Vec<(I
         nodes.push((node.clone(), *rand_x, *rand_y));
     }
 
-    return nodes;
+    nodes
 }
 
 pub fn create_grid(nodes: &Vec<(Identity, usize, usize)>) -> Vec<Vec<Identity>> {
@@ -122,7 +114,7 @@ pub fn create_grid(nodes: &Vec<(Identity, usize, usize)>) -> Vec<Vec<Identity>>
         grid[node.2][node.1] = node.0.clone();
     }
 
-    return grid;
+    grid
 }
 
 pub fn display_nodes(nodes: Vec<(Identity, usize, usize)>) {
@@ -138,16 +130,8 @@ pub fn display_grid(grid: Vec<Vec<Identity>>) {
 
 pub fn create_beacon_grid(nodes: usize, grid: (usize, usize)) -> PolarCoordinates {
     let beacon_nodes = create_nodes_with_positions(nodes, grid);
-    let beacons: Vec<Identity> = beacon_nodes.iter().cloned().map(|x| {x.0.clone()}).collect();
+    let beacons: Vec<Identity> = beacon_nodes.iter().map(|x| {x.0.clone()}).collect();
     let beacon_graph = get_distance_graph(beacon_nodes);
 
-    return beacon_graph.get_position_graph(&beacons, &beam_deviation_filter);
+    beacon_graph.get_position_graph(&beacons, &BeamDeviationFilter::default()).expect("beacon graph should solve")
 }
-
-pub fn create_beacon_polar(nodes: usize, max_distance: f64) -> PolarCoordinates {
-    let mut rng = thread_rng();
-    let mut beacons: Vec<Identity> = vec![];
-    let mut beacon_graph = DistanceGraph::new();
-
-    return beacon_graph.get_position_graph(&beacons, &beam_deviation_filter);
-}
\ No newline at end of file
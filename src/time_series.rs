@@ -6,36 +6,221 @@
 // * Prefer timeliness of read to accuracy of the "time window"
 
 use super::location::Location;
+use crate::identity::Identity;
 
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use datetime::{DatePiece, Duration, LocalDateTime, TimePiece};
+use datetime::{DatePiece, LocalDateTime, TimePiece};
 
+const DEFAULT_BUCKET_WIDTH: Duration = Duration::from_millis(100);
+const DEFAULT_RETENTION: Duration = Duration::from_secs(60);
+
+// Locations are grouped into fixed width buckets keyed by the bucket's index since the epoch.  Reads and expiry work on whole buckets first, and only look at individual timestamps on the edges of a window.
 pub struct LocationTimeSeries {
-    data: Vec<Vec<Location>>
+    bucket_width: Duration,
+    retention: Duration,
+    newest: Option<SystemTime>,
+    data: BTreeMap<u128, Vec<(SystemTime, Location)>>,
+    latest: HashMap<Identity, (SystemTime, Location)>,
 }
 
 impl LocationTimeSeries {
     pub fn new() -> LocationTimeSeries {
-        let init_time = LocalDateTime::now();
+        LocationTimeSeries::with_retention(DEFAULT_BUCKET_WIDTH, DEFAULT_RETENTION)
+    }
 
+    pub fn with_retention(bucket_width: Duration, retention: Duration) -> LocationTimeSeries {
         LocationTimeSeries {
-            data: vec![]
+            // A zero width bucket would divide by zero when indexing.
+            bucket_width: bucket_width.max(Duration::from_millis(1)),
+            retention,
+            newest: None,
+            data: BTreeMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    pub fn get_path(&self, datetime: LocalDateTime) -> Arc<str> {
+        get_path(datetime)
+    }
+
+    fn get_bucket(&self, timestamp: SystemTime) -> u128 {
+        get_bucket(timestamp, self.bucket_width)
+    }
+
+    // Inserting also expires anything that has fallen out of the retention window of the newest timestamp seen, so callers never have to schedule cleanup themselves.  A location already older than that window is dropped rather than stored, and false is returned.
+    pub fn insert(&mut self, timestamp: SystemTime, location: Location) -> bool {
+        if self.is_expired(timestamp) {
+            return false;
+        }
+
+        let bucket = self.get_bucket(timestamp);
+
+        match self.latest.get(&location.node) {
+            Some((latest_time, _)) if *latest_time > timestamp => {},
+            _ => {
+                self.latest.insert(location.node.clone(), (timestamp, location.clone()));
+            }
+        }
+
+        self.data.entry(bucket).or_default().push((timestamp, location));
+
+        if self.newest.is_none_or(|newest| timestamp > newest) {
+            self.newest = Some(timestamp);
+            self.expire(timestamp);
+        }
+
+        true
+    }
+
+    // Uses the same bucket-granular cutoff as expire, so a late location is accepted exactly when its bucket is still around.
+    fn is_expired(&self, timestamp: SystemTime) -> bool {
+        self.newest.and_then(|newest| newest.checked_sub(self.retention)).is_some_and(|cutoff| self.get_bucket(timestamp) < self.get_bucket(cutoff))
+    }
+
+    // Returns every location with a timestamp in [start, end), ordered by bucket.  Locations within a bucket are kept in insertion order.
+    pub fn get(&self, start: SystemTime, end: SystemTime) -> Vec<&Location> {
+        if end <= start {
+            return vec![];
         }
+
+        self.data
+            .range(self.get_bucket(start)..=self.get_bucket(end))
+            .flat_map(|(_, bucket)| bucket.iter())
+            .filter(|(timestamp, _)| *timestamp >= start && *timestamp < end)
+            .map(|(_, location)| location)
+            .collect()
+    }
+
+    pub fn get_latest(&self, node: &Identity) -> Option<&Location> {
+        self.latest.get(node).map(|(_, location)| location)
+    }
+
+    pub fn get_latest_time(&self, node: &Identity) -> Option<SystemTime> {
+        self.latest.get(node).map(|(timestamp, _)| *timestamp)
+    }
+
+    pub fn get_nodes(&self) -> Vec<Identity> {
+        self.latest.keys().cloned().collect()
+    }
+
+    // Drops every bucket that ends before `now - retention`.  Buckets that straddle the cutoff are kept whole, preferring a quick purge over an exact window, and the latest locations follow the same buckets so get_latest never knows a node the data has forgotten or forgets one it still holds.
+    pub fn expire(&mut self, now: SystemTime) {
+        let cutoff = match now.checked_sub(self.retention) {
+            Some(cutoff) => cutoff,
+            None => return,
+        };
+
+        let cutoff_bucket = self.get_bucket(cutoff);
+        self.data = self.data.split_off(&cutoff_bucket);
+        let bucket_width = self.bucket_width;
+        self.latest.retain(|_, (timestamp, _)| get_bucket(*timestamp, bucket_width) >= cutoff_bucket);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.values().map(|bucket| bucket.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+fn get_bucket(timestamp: SystemTime, bucket_width: Duration) -> u128 {
+    let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    since_epoch.as_millis() / bucket_width.as_millis()
+}
+
+pub fn get_path(datetime: LocalDateTime) -> Arc<str> {
+    // Creating a path to determine location for storing location data to
+    // objectstore.  This will use something like the following as the
+    // directory structure:
+    //    YYYY/MM/DD/hh/mm/ss/mmm/iii/nnn
+
+    // This allows us to easily parse all files in a directory that is
+    // older than some number of seconds or milliseconds.  Every piece is zero
+    // padded so the directories sort in time order.
+    format!("{:04}/{:02}/{:02}/{:02}/{:02}/{:02}/{:03}", datetime.year(), datetime.month().months_from_january() + 1, datetime.day(), datetime.hour(), datetime.minute(), datetime.second(), datetime.millisecond()).into()
+}
+
+impl Default for LocationTimeSeries {
+    fn default() -> Self {
+        LocationTimeSeries::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    fn location(node: &str) -> Location {
+        Location::from_distances(node.into(), vec![])
+    }
+
+    #[test]
+    fn get_returns_half_open_window() {
+        let mut series = LocationTimeSeries::with_retention(Duration::from_millis(10), Duration::from_secs(60));
+
+        series.insert(at(1_000), location("A"));
+        series.insert(at(1_005), location("B"));
+        series.insert(at(1_020), location("C"));
+
+        let window: Vec<&str> = series.get(at(1_005), at(1_020)).iter().map(|l| &*l.node).collect();
+        assert_eq!(window, vec!["B"]);
+        assert_eq!(series.get(at(1_000), at(1_021)).len(), 3);
+    }
+
+    #[test]
+    fn latest_ignores_out_of_order_inserts() {
+        let mut series = LocationTimeSeries::new();
+        let node: Identity = "A".into();
+
+        series.insert(at(2_000), location("A"));
+        series.insert(at(1_000), location("A"));
+
+        assert_eq!(series.get_latest_time(&node), Some(at(2_000)));
+        assert_eq!(series.len(), 2);
     }
 
-    fn get_path(&self, datetime: LocalDateTime) -> Arc<str> {
-        // Creating a path to determine location for storing location data to 
-        // objectstore.  This will use something like the following as the 
-        // directory structure:
-        //    YYYY/MM/DD/hh/mm/ss/mmm/iii/nnn
-        
-        // This allows us to easily parse all files in a directory that is 
-        // older than some number of seconds or milliseconds.
-        format!("{:?}/{:?}/{:?}/{:?}/{:?}/{:?}/{:?}", datetime.year(), datetime.month(), datetime.day(), datetime.hour(), datetime.minute(), datetime.second(), datetime.millisecond()).into()
+    #[test]
+    fn insert_expires_stale_buckets() {
+        let mut series = LocationTimeSeries::with_retention(Duration::from_millis(10), Duration::from_millis(100));
+
+        series.insert(at(1_000), location("A"));
+        series.insert(at(1_050), location("B"));
+        series.insert(at(1_200), location("C"));
+
+        assert_eq!(series.len(), 1);
+        assert!(series.get_latest(&"A".into()).is_none());
+        assert!(series.get_latest(&"C".into()).is_some());
+
+        // Late, but still inside the window, versus already past it.
+        assert!(series.insert(at(1_150), location("D")));
+        assert!(!series.insert(at(1_050), location("E")));
+        assert_eq!(series.len(), 2);
+        assert!(series.get_latest(&"E".into()).is_none());
     }
 
-    pub fn get(&self, start: LocalDateTime) {
-        
+    #[test]
+    fn expiry_keeps_straddling_buckets_whole() {
+        let mut series = LocationTimeSeries::with_retention(Duration::from_millis(10), Duration::from_millis(100));
+
+        series.insert(at(1_101), location("A"));
+        series.insert(at(1_205), location("B"));
+
+        // The cutoff is 1_105, inside A's bucket, so A stays in both the data and the latest locations.
+        assert_eq!(series.len(), 2);
+        assert_eq!(series.get_latest_time(&"A".into()), Some(at(1_101)));
+
+        // A late arrival in that same bucket is kept too, one bucket earlier isn't.
+        assert!(series.insert(at(1_100), location("C")));
+        assert!(!series.insert(at(1_099), location("D")));
+        assert!(series.get_latest(&"C".into()).is_some());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,72 @@
+// Serde adapters for timestamps.  Serde's own SystemTime format is a struct of
+// seconds and nanoseconds, which is awkward to read in a dump and doesn't match
+// anything else we send, so timestamps go out as whole milliseconds since the Unix
+// epoch, the same as the text and binary signal formats.  Use them with
+// #[serde(with = "crate::timestamp::millis")].
+
+pub mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        serializer.serialize_u64(millis)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let millis = u64::deserialize(deserializer)?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+    }
+}
+
+// The same, for the timestamp column of a DistanceGraph.
+pub mod millis_vec {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime};
+
+    pub fn serialize<S: Serializer>(times: &[SystemTime], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(times.iter().map(|time| time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SystemTime>, D::Error> {
+        let millis = Vec::<u64>::deserialize(deserializer)?;
+        Ok(millis.into_iter().map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::agent::Agent;
+    use crate::beacon::BeaconSignal;
+    use crate::location::{DistanceGraph, Location, MomentEdge};
+    use crate::polar::{PolarCoordinates, Radial};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn core_types_round_trip_through_json() {
+        let at = |millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+
+        let edges = vec![MomentEdge::new("A".into(), "B".into(), 5.0, at(1_000)), MomentEdge::new("A".into(), "C".into(), 7.5, at(2_000))];
+        let graph = DistanceGraph::from_edges(edges.clone());
+        let json = serde_json::to_string(&graph).unwrap();
+        assert!(json.contains("\"timestamps\":[1000,2000]"), "{}", json);
+
+        let read: DistanceGraph = serde_json::from_str(&json).unwrap();
+        assert_eq!((read.lefts, read.rights, read.distances, read.timestamps), (graph.lefts, graph.rights, graph.distances, graph.timestamps));
+
+        let location: Location = serde_json::from_str(&serde_json::to_string(&Location::from_distances("A".into(), edges)).unwrap()).unwrap();
+        assert_eq!(location.distances[1].timestamp, at(2_000));
+
+        let signal: BeaconSignal = serde_json::from_str(r#"{"id":"B1","distance":3.5,"timestamp":1234}"#).unwrap();
+        assert_eq!((&*signal.id, signal.distance, signal.timestamp), ("B1", 3.5, at(1_234)));
+
+        let mut coordinates = PolarCoordinates::new("A".into());
+        coordinates.add_radial(Radial { id: "B".into(), radius: 5.0, angle: 1.0 });
+        let read: PolarCoordinates = serde_json::from_str(&serde_json::to_string(&coordinates).unwrap()).unwrap();
+        assert_eq!(read[&"B".into()], coordinates[&"B".into()]);
+
+        let agent = Agent::new("R1".into(), 0, Radial { id: "R1".into(), radius: 2.0, angle: 0.5 }, 0.01);
+        let read: Agent = serde_json::from_str(&serde_json::to_string(&agent).unwrap()).unwrap();
+        assert_eq!(read.position, agent.position);
+    }
+}
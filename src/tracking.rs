@@ -0,0 +1,269 @@
+// Constant velocity Kalman tracking for moving nodes.  Each tracked node keeps a
+// state of [x, y, vx, vy] in the frame of a solved set of anchors.  Positions
+// (from a solved graph) are folded in with a linear update, and raw ranges to
+// anchors are folded in with an extended Kalman update, linearized around the
+// current estimate.
+
+use crate::identity::Identity;
+use crate::location::MomentEdge;
+use crate::matrix::Matrix;
+use crate::point::Point2;
+use crate::polar::{PolarCoordinates, Radial};
+use crate::uncertainty::ErrorEllipse;
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+const STATE_SIZE: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct TrackState {
+    pub id: Identity,
+    pub position: (f64, f64),
+    pub velocity: (f64, f64),
+    // Covariance of [x, y, vx, vy].
+    pub covariance: Matrix,
+    pub timestamp: SystemTime,
+}
+
+impl TrackState {
+    // The 1 sigma ellipse of the position block of the covariance.
+    pub fn get_error_ellipse(&self) -> ErrorEllipse {
+        ErrorEllipse::from_covariance(&self.covariance)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KalmanTracker {
+    pub id: Identity,
+    // Spectral density of the white noise acceleration driving the model, in distance^2 / s^3.
+    pub process_noise: f64,
+    state: Matrix,
+    covariance: Matrix,
+    timestamp: SystemTime,
+}
+
+impl KalmanTracker {
+    pub fn new(id: Identity, position: (f64, f64), position_variance: f64, process_noise: f64, timestamp: SystemTime) -> KalmanTracker {
+        let mut covariance = Matrix::identity(STATE_SIZE).scale(position_variance);
+        // Nothing is known about the velocity yet, so start it off as loose as the position.
+        covariance[(2, 2)] = position_variance.max(1.0);
+        covariance[(3, 3)] = position_variance.max(1.0);
+
+        KalmanTracker {
+            id,
+            process_noise,
+            state: Matrix::column(&[position.0, position.1, 0.0, 0.0]),
+            covariance,
+            timestamp,
+        }
+    }
+
+    pub fn get_state(&self) -> TrackState {
+        TrackState {
+            id: self.id.clone(),
+            position: (self.state[(0, 0)], self.state[(1, 0)]),
+            velocity: (self.state[(2, 0)], self.state[(3, 0)]),
+            covariance: self.covariance.clone(),
+            timestamp: self.timestamp,
+        }
+    }
+
+    pub fn get_position(&self) -> Radial {
+        Point2::new(self.state[(0, 0)], self.state[(1, 0)]).to_radial(self.id.clone())
+    }
+
+    // Moves the state forward to `timestamp`.  Measurements that arrive out of order are applied to the current state without rewinding it.
+    pub fn predict(&mut self, timestamp: SystemTime) {
+        let dt = match timestamp.duration_since(self.timestamp) {
+            Ok(dt) => dt.as_secs_f64(),
+            Err(_) => return,
+        };
+
+        if dt == 0.0 {
+            return;
+        }
+
+        let mut transition = Matrix::identity(STATE_SIZE);
+        transition[(0, 2)] = dt;
+        transition[(1, 3)] = dt;
+
+        let q = self.process_noise;
+        let mut noise = Matrix::zeros(STATE_SIZE, STATE_SIZE);
+
+        for axis in 0..2 {
+            noise[(axis, axis)] = q * dt.powi(3) / 3.0;
+            noise[(axis, axis + 2)] = q * dt.powi(2) / 2.0;
+            noise[(axis + 2, axis)] = q * dt.powi(2) / 2.0;
+            noise[(axis + 2, axis + 2)] = q * dt;
+        }
+
+        self.state = &transition * &self.state;
+        self.covariance = &(&(&transition * &self.covariance) * &transition.transpose()) + &noise;
+        self.timestamp = timestamp;
+    }
+
+    pub fn update_position(&mut self, position: (f64, f64), variance: f64, timestamp: SystemTime) {
+        self.predict(timestamp);
+
+        let mut observation = Matrix::zeros(2, STATE_SIZE);
+        observation[(0, 0)] = 1.0;
+        observation[(1, 1)] = 1.0;
+
+        let measurement = Matrix::column(&[position.0, position.1]);
+        let residual = &measurement - &(&observation * &self.state);
+
+        self.correct(&observation, &residual, &Matrix::identity(2).scale(variance));
+    }
+
+    // Extended Kalman update for a range measured to an anchor at a known position.
+    pub fn update_range(&mut self, anchor: (f64, f64), range: f64, variance: f64, timestamp: SystemTime) {
+        self.predict(timestamp);
+
+        let dx = self.state[(0, 0)] - anchor.0;
+        let dy = self.state[(1, 0)] - anchor.1;
+        let predicted = (dx.powi(2) + dy.powi(2)).sqrt();
+
+        // The range has no usable gradient while the estimate is sitting on the anchor.
+        if predicted < 1e-9 {
+            return;
+        }
+
+        let mut observation = Matrix::zeros(1, STATE_SIZE);
+        observation[(0, 0)] = dx / predicted;
+        observation[(0, 1)] = dy / predicted;
+
+        let residual = Matrix::column(&[range - predicted]);
+
+        self.correct(&observation, &residual, &Matrix::identity(1).scale(variance));
+    }
+
+    fn correct(&mut self, observation: &Matrix, residual: &Matrix, noise: &Matrix) {
+        let innovation = &(&(observation * &self.covariance) * &observation.transpose()) + noise;
+
+        let innovation_inverse = match innovation.inverse() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let gain = &(&self.covariance * &observation.transpose()) * &innovation_inverse;
+
+        self.state = &self.state + &(&gain * residual);
+        self.covariance = &(&Matrix::identity(STATE_SIZE) - &(&gain * observation)) * &self.covariance;
+    }
+}
+
+// Keeps a KalmanTracker for every node seen against a set of solved anchor positions.
+pub struct SwarmTracker {
+    pub anchors: PolarCoordinates,
+    pub measurement_variance: f64,
+    pub process_noise: f64,
+    pub tracks: HashMap<Identity, KalmanTracker>,
+}
+
+impl SwarmTracker {
+    pub fn new(anchors: PolarCoordinates, measurement_variance: f64, process_noise: f64) -> SwarmTracker {
+        SwarmTracker {
+            anchors,
+            measurement_variance,
+            process_noise,
+            tracks: HashMap::new(),
+        }
+    }
+
+    fn get_anchor(&self, id: &Identity) -> Option<(f64, f64)> {
+        if *id == self.anchors.origin {
+            return Some((0.0, 0.0));
+        }
+
+        self.anchors.get(id).map(|r| r.get_cartesian())
+    }
+
+    // Folds in a range between an anchor and a tracked node.  Edges between two anchors, or two nodes that aren't anchors, don't say anything about a tracked position and are ignored.  Returns whether the edge was used.
+    pub fn add_edge(&mut self, edge: &MomentEdge) -> bool {
+        let (anchor, node) = match (self.get_anchor(&edge.left), self.get_anchor(&edge.right)) {
+            (Some(anchor), None) => (anchor, edge.right.clone()),
+            (None, Some(anchor)) => (anchor, edge.left.clone()),
+            _ => return false,
+        };
+
+        let variance = self.measurement_variance;
+        let process_noise = self.process_noise;
+
+        // A range only puts a new node somewhere on a circle around the anchor, so it starts on that circle with a covariance as wide as the circle.
+        let tracker = self.tracks.entry(node.clone()).or_insert_with(|| {
+            KalmanTracker::new(node, (anchor.0 + edge.distance, anchor.1), edge.distance.powi(2).max(variance), process_noise, edge.timestamp)
+        });
+
+        tracker.update_range(anchor, edge.distance, variance, edge.timestamp);
+        true
+    }
+
+    pub fn extend(&mut self, edges: &[MomentEdge]) {
+        for edge in edges {
+            self.add_edge(edge);
+        }
+    }
+
+    // Folds in an already solved position, e.g. a radial out of get_position_graph in the same frame as the anchors.
+    pub fn add_position(&mut self, position: &Radial, timestamp: SystemTime) {
+        let variance = self.measurement_variance;
+        let process_noise = self.process_noise;
+        let point = position.get_cartesian();
+
+        self.tracks
+            .entry(position.id.clone())
+            .or_insert_with(|| KalmanTracker::new(position.id.clone(), point, variance, process_noise, timestamp))
+            .update_position(point, variance, timestamp);
+    }
+
+    pub fn get_state(&self, id: &Identity) -> Option<TrackState> {
+        self.tracks.get(id).map(|t| t.get_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn position_updates_recover_velocity() {
+        let mut tracker = KalmanTracker::new("A".into(), (0.0, 0.0), 1.0, 0.01, at(0));
+
+        for i in 1..=50 {
+            let t = i as f64 * 0.1;
+            tracker.update_position((2.0 * t, -t), 0.01, at(i * 100));
+        }
+
+        let state = tracker.get_state();
+        assert!((state.velocity.0 - 2.0).abs() < 0.1, "{:?}", state.velocity);
+        assert!((state.velocity.1 + 1.0).abs() < 0.1, "{:?}", state.velocity);
+        assert!((state.position.0 - 10.0).abs() < 0.1, "{:?}", state.position);
+    }
+
+    #[test]
+    fn range_updates_converge_on_anchors() {
+        let mut anchors = PolarCoordinates::new("O".into());
+        anchors.add_radial(Radial { id: "B".into(), radius: 10.0, angle: 0.0 });
+        anchors.add_radial(Radial { id: "C".into(), radius: 10.0, angle: std::f64::consts::FRAC_PI_2 });
+
+        let mut tracker = SwarmTracker::new(anchors, 0.01, 0.01);
+        let target: (f64, f64) = (3.0, 4.0);
+        let anchor_positions = [("O", (0.0, 0.0)), ("B", (10.0, 0.0)), ("C", (0.0, 10.0))];
+
+        for i in 0..60 {
+            let (anchor, position) = anchor_positions[i % 3];
+            let range = ((target.0 - position.0).powi(2) + (target.1 - position.1).powi(2)).sqrt();
+            assert!(tracker.add_edge(&MomentEdge::new(anchor.into(), "A".into(), range, at(i as u64 * 10))));
+        }
+
+        let state = tracker.get_state(&"A".into()).unwrap();
+        assert!((state.position.0 - target.0).abs() < 0.1, "{:?}", state.position);
+        assert!((state.position.1 - target.1).abs() < 0.1, "{:?}", state.position);
+    }
+}
@@ -0,0 +1,155 @@
+// How much a position fix can be trusted.  The error of a range-only fix is the
+// ranging error stretched by the geometry of the beacons it was ranged against:
+// beacons spread all around the node pin it down, beacons bunched up in one
+// direction leave it free to slide.  The dilution of precision is that stretch on
+// its own, and the covariance is the stretch times the measurement variance.
+
+use crate::matrix::Matrix;
+use crate::point::Point2;
+use crate::polar::Angle;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorEllipse {
+    // 1 sigma half axes, so about 39% of fixes land inside it in 2D.
+    pub semi_major: f64,
+    pub semi_minor: f64,
+    // Angle of the major axis from the x axis, in [0, PI).
+    pub orientation: Angle,
+}
+
+impl ErrorEllipse {
+    // From a 2x2 position covariance.
+    pub fn from_covariance(covariance: &Matrix) -> ErrorEllipse {
+        let (a, b, c) = (covariance[(0, 0)], covariance[(0, 1)], covariance[(1, 1)]);
+        let mean = (a + c) / 2.0;
+        let spread = (((a - c) / 2.0).powi(2) + b.powi(2)).sqrt();
+
+        ErrorEllipse {
+            semi_major: (mean + spread).max(0.0).sqrt(),
+            semi_minor: (mean - spread).max(0.0).sqrt(),
+            orientation: (0.5 * (2.0 * b).atan2(a - c)).rem_euclid(std::f64::consts::PI),
+        }
+    }
+
+    // Scales the ellipse out to another confidence, e.g. 2.4477 for 95% in 2D.
+    pub fn scale(&self, sigma: f64) -> ErrorEllipse {
+        ErrorEllipse {
+            semi_major: self.semi_major * sigma,
+            semi_minor: self.semi_minor * sigma,
+            orientation: self.orientation,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Uncertainty {
+    // 2x2 covariance of the x/y position.
+    pub covariance: Matrix,
+    pub ellipse: ErrorEllipse,
+    // Horizontal dilution of precision, the position error per unit of ranging error.
+    pub hdop: f64,
+    // Geometric dilution of precision, which also solves for a range bias shared by every measurement (e.g. an uncalibrated antenna delay).  Infinite when the beacons can't separate the bias from the position.
+    pub gdop: f64,
+}
+
+impl Uncertainty {
+    pub fn meets(&self, threshold: &FixThreshold) -> bool {
+        self.hdop <= threshold.max_hdop && self.ellipse.semi_major <= threshold.max_error
+    }
+}
+
+// The worst fix a caller is willing to act on.
+#[derive(Clone, Debug)]
+pub struct FixThreshold {
+    pub max_hdop: f64,
+    // Largest 1 sigma semi major axis.
+    pub max_error: f64,
+}
+
+impl Default for FixThreshold {
+    fn default() -> Self {
+        FixThreshold {
+            max_hdop: 5.0,
+            max_error: 1.0,
+        }
+    }
+}
+
+// Unit vectors from each beacon towards the position, which is how a small move of the position changes each range.
+fn get_geometry(position: Point2, beacons: &[Point2], with_bias: bool) -> Matrix {
+    let columns = if with_bias { 3 } else { 2 };
+    let mut geometry = Matrix::zeros(beacons.len(), columns);
+
+    for (i, beacon) in beacons.iter().enumerate() {
+        let offset = position - *beacon;
+        let range = offset.length().max(1e-12);
+        geometry[(i, 0)] = offset.x / range;
+        geometry[(i, 1)] = offset.y / range;
+
+        if with_bias {
+            geometry[(i, 2)] = 1.0;
+        }
+    }
+
+    geometry
+}
+
+fn get_dilution(position: Point2, beacons: &[Point2], with_bias: bool) -> Option<Matrix> {
+    let geometry = get_geometry(position, beacons, with_bias);
+    (&geometry.transpose() * &geometry).inverse()
+}
+
+// The uncertainty of a fix at position from ranges to the given beacons, each with the given variance.  Returns None when the beacons can't pin the position down (fewer than two, or all in line with the position).
+pub fn get_uncertainty(position: Point2, beacons: &[Point2], variance: f64) -> Option<Uncertainty> {
+    if beacons.len() < 2 {
+        return None;
+    }
+
+    let dilution = get_dilution(position, beacons, false)?;
+    let covariance = dilution.scale(variance);
+
+    let gdop = match get_dilution(position, beacons, true) {
+        Some(biased) => (biased[(0, 0)] + biased[(1, 1)] + biased[(2, 2)]).sqrt(),
+        None => f64::INFINITY,
+    };
+
+    Some(Uncertainty {
+        ellipse: ErrorEllipse::from_covariance(&covariance),
+        covariance,
+        hdop: (dilution[(0, 0)] + dilution[(1, 1)]).sqrt(),
+        gdop,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrounded_fix_is_better_than_one_sided() {
+        let square = [Point2::new(0.0, 0.0), Point2::new(10.0, 0.0), Point2::new(10.0, 10.0), Point2::new(0.0, 10.0)];
+        let centered = get_uncertainty(Point2::new(5.0, 5.0), &square, 0.25).unwrap();
+
+        assert!((centered.hdop - 1.0).abs() < 1e-9, "{:?}", centered);
+        assert!((centered.ellipse.semi_major - 0.5 * 0.5_f64.sqrt()).abs() < 1e-9);
+        assert!(centered.gdop.is_finite());
+
+        let outside = get_uncertainty(Point2::new(50.0, 5.0), &square, 0.25).unwrap();
+        assert!(outside.hdop > centered.hdop);
+        assert!(!outside.meets(&FixThreshold::default()) && centered.meets(&FixThreshold::default()));
+
+        // Every beacon on the x axis with the position on it as well leaves y free.
+        let line = [Point2::new(0.0, 0.0), Point2::new(10.0, 0.0), Point2::new(20.0, 0.0)];
+        assert!(get_uncertainty(Point2::new(30.0, 0.0), &line, 0.25).is_none());
+    }
+
+    #[test]
+    fn ellipse_follows_covariance() {
+        let covariance = Matrix::from_rows(&[vec![4.0, 0.0], vec![0.0, 1.0]]);
+        let ellipse = ErrorEllipse::from_covariance(&covariance);
+        assert_eq!(ellipse, ErrorEllipse { semi_major: 2.0, semi_minor: 1.0, orientation: 0.0 });
+
+        let turned = ErrorEllipse::from_covariance(&Matrix::from_rows(&[vec![1.0, 0.0], vec![0.0, 4.0]]));
+        assert!((turned.orientation - std::f64::consts::PI / 2.0).abs() < 1e-12);
+    }
+}
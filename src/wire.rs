@@ -0,0 +1,218 @@
+// Binary framing for signals sent over the radio.  Every frame is
+//
+//     [version: u8] [payload length: varint] [payload] [crc32 of everything before it: u32 le]
+//
+// Lengths are LEB128 varints, so short identities and payloads cost a single byte.
+// Newer versions may only append fields to the end of a payload, which lets older
+// firmware keep reading the fields it knows and skip the rest instead of rejecting
+// the whole frame.
+
+use crate::error::{Error, Result};
+use crate::identity::Identity;
+
+pub const WIRE_VERSION: u8 = 1;
+
+const CRC_SIZE: usize = 4;
+
+// CRC-32 (IEEE 802.3, the one zlib uses), bit by bit.  Frames are small enough that a lookup table isn't worth carrying around.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { buffer: vec![] }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+
+        self.buffer.push(value as u8);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn write_identity(&mut self, id: &Identity) {
+        self.write_bytes(id.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn take(&mut self, count: usize, field: &str) -> Result<&'a [u8]> {
+        if self.remaining() < count {
+            return Err(Error::Parse(format!("frame ends in the middle of {}", field)));
+        }
+
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self, field: &str) -> Result<u8> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    pub fn read_varint(&mut self, field: &str) -> Result<u64> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8(field)?;
+            value |= ((byte & 0x7F) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::Parse(format!("{} is longer than a u64", field)))
+    }
+
+    pub fn read_u64(&mut self, field: &str) -> Result<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8, field)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_f64(&mut self, field: &str) -> Result<f64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8, field)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, field: &str) -> Result<&'a [u8]> {
+        let length = self.read_varint(field)?;
+        let length = usize::try_from(length).map_err(|_| Error::Parse(format!("{} is too long", field)))?;
+        self.take(length, field)
+    }
+
+    pub fn read_identity(&mut self, field: &str) -> Result<Identity> {
+        let bytes = self.read_bytes(field)?;
+        let id = std::str::from_utf8(bytes).map_err(|e| Error::Parse(format!("{} isn't utf-8: {}", field, e)))?;
+        Ok(id.into())
+    }
+}
+
+// Wraps a payload in a frame at the current version.
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.write_u8(WIRE_VERSION);
+    writer.write_bytes(payload);
+
+    let mut bytes = writer.into_bytes();
+    let crc = crc32(&bytes);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+// Checks the CRC and returns the version and payload of a frame.  Any version from 1 up is accepted, since newer payloads only ever add fields on the end.
+pub fn unframe(data: &[u8]) -> Result<(u8, &[u8])> {
+    if data.len() < CRC_SIZE + 2 {
+        return Err(Error::Parse(format!("frame of {} bytes is too short", data.len())));
+    }
+
+    let (body, crc) = data.split_at(data.len() - CRC_SIZE);
+    let expected = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+
+    if crc32(body) != expected {
+        return Err(Error::Parse("frame failed its crc check".into()));
+    }
+
+    let mut reader = Reader::new(body);
+    let version = reader.read_u8("version")?;
+
+    if version == 0 {
+        return Err(Error::Parse("frame version 0 is not valid".into()));
+    }
+
+    let payload = reader.read_bytes("payload")?;
+
+    if reader.remaining() != 0 {
+        return Err(Error::Parse(format!("{} bytes after the payload", reader.remaining())));
+    }
+
+    Ok((version, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_and_varint_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut writer = Writer::new();
+            writer.write_varint(value);
+            let bytes = writer.into_bytes();
+
+            assert_eq!(Reader::new(&bytes).read_varint("value").unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn frames_reject_corruption_and_truncation() {
+        let framed = frame(b"payload");
+        assert_eq!(unframe(&framed).unwrap(), (WIRE_VERSION, &b"payload"[..]));
+
+        let mut corrupted = framed.clone();
+        corrupted[3] ^= 0x01;
+        assert!(unframe(&corrupted).is_err());
+
+        for length in 0..framed.len() {
+            assert!(unframe(&framed[..length]).is_err(), "{} bytes", length);
+        }
+    }
+}